@@ -0,0 +1,44 @@
+#[macro_use]
+extern crate criterion;
+
+use criterion::{Bencher, Criterion, Fun};
+use sourmash::{max_hash_for_scaled, KmerMinHash};
+
+fn random_dna(len: usize) -> Vec<u8> {
+    // small LCG, to get the same sequence in every run
+    let mut state: u64 = 42;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            b"ACGTacgt"[(state >> 61) as usize]
+        })
+        .collect()
+}
+
+fn add_sequence_bench(c: &mut Criterion) {
+    let seq = random_dna(100_000);
+
+    let num = Fun::new("num", move |b: &mut Bencher, seq: &Vec<u8>| {
+        b.iter(|| {
+            let mut mh = KmerMinHash::new(500, 31, false, 42, 0, false);
+            mh.add_sequence(seq, false).unwrap();
+            mh
+        })
+    });
+
+    let scaled = Fun::new("scaled", move |b: &mut Bencher, seq: &Vec<u8>| {
+        b.iter(|| {
+            let mut mh = KmerMinHash::new(0, 31, false, 42, max_hash_for_scaled(1000), true);
+            mh.add_sequence(seq, false).unwrap();
+            mh
+        })
+    });
+
+    let functions = vec![num, scaled];
+    c.bench_functions("add_sequence_dna", functions, seq);
+}
+
+criterion_group!(benches, add_sequence_bench);
+criterion_main!(benches);
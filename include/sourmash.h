@@ -7,6 +7,15 @@
 #include <stdlib.h>
 #include <stdbool.h>
 
+/*
+ * Hash functions used to turn k-mers into the hashes stored in a sketch.
+ */
+enum HashFunction {
+  HASH_FUNCTION_MURMUR64 = 1,
+  HASH_FUNCTION_XX_HASH64 = 2,
+};
+typedef uint32_t HashFunction;
+
 enum SourmashErrorCode {
   SOURMASH_ERROR_CODE_NO_ERROR = 0,
   SOURMASH_ERROR_CODE_PANIC = 1,
@@ -17,8 +26,25 @@ enum SourmashErrorCode {
   SOURMASH_ERROR_CODE_MISMATCH_D_N_A_PROT = 102,
   SOURMASH_ERROR_CODE_MISMATCH_MAX_HASH = 103,
   SOURMASH_ERROR_CODE_MISMATCH_SEED = 104,
+  SOURMASH_ERROR_CODE_INVALID_DOWNSAMPLE = 105,
+  SOURMASH_ERROR_CODE_NEEDS_ABUNDANCE_TRACKING = 106,
+  SOURMASH_ERROR_CODE_INVALID_TRANSLATION_TABLE = 107,
+  SOURMASH_ERROR_CODE_MISMATCH_HASH_FUNCTION = 108,
+  SOURMASH_ERROR_CODE_INVALID_HASH_FUNCTION = 109,
+  SOURMASH_ERROR_CODE_INVALID_MOLECULE = 110,
+  SOURMASH_ERROR_CODE_INVALID_HLL_PRECISION = 111,
+  SOURMASH_ERROR_CODE_MISMATCH_HLL_PRECISION = 112,
+  SOURMASH_ERROR_CODE_NON_EMPTY_MIN_HASH = 113,
   SOURMASH_ERROR_CODE_INVALID_D_N_A = 1101,
   SOURMASH_ERROR_CODE_INVALID_PROT = 1102,
+  SOURMASH_ERROR_CODE_INVALID_RECORD = 1103,
+  SOURMASH_ERROR_CODE_INVALID_PICKLIST = 1104,
+  SOURMASH_ERROR_CODE_INVALID_MD5SUM = 1201,
+  SOURMASH_ERROR_CODE_UNSORTED_HASHES = 1202,
+  SOURMASH_ERROR_CODE_INVALID_ABUNDANCES = 1203,
+  SOURMASH_ERROR_CODE_INVALID_NUM = 1204,
+  SOURMASH_ERROR_CODE_INVALID_MAX_HASH = 1205,
+  SOURMASH_ERROR_CODE_INVALID_BINARY_SIGNATURE = 1206,
   SOURMASH_ERROR_CODE_IO = 100001,
   SOURMASH_ERROR_CODE_UTF8_ERROR = 100002,
   SOURMASH_ERROR_CODE_PARSE_INT = 100003,
@@ -47,14 +73,34 @@ void kmerminhash_add_from(KmerMinHash *ptr, const KmerMinHash *other);
 
 void kmerminhash_add_hash(KmerMinHash *ptr, uint64_t h);
 
+void kmerminhash_add_protein(KmerMinHash *ptr, const char *sequence);
+
 void kmerminhash_add_sequence(KmerMinHash *ptr, const char *sequence, bool force);
 
 void kmerminhash_add_word(KmerMinHash *ptr, const char *word);
 
+double kmerminhash_angular_similarity(KmerMinHash *ptr, const KmerMinHash *other);
+
+uint64_t kmerminhash_cardinality(KmerMinHash *ptr);
+
 double kmerminhash_compare(KmerMinHash *ptr, const KmerMinHash *other);
 
+double kmerminhash_contained_by(KmerMinHash *ptr, const KmerMinHash *other);
+
+double kmerminhash_containment_ani(KmerMinHash *ptr, const KmerMinHash *other);
+
 uint64_t kmerminhash_count_common(KmerMinHash *ptr, const KmerMinHash *other);
 
+bool kmerminhash_dayhoff(KmerMinHash *ptr);
+
+KmerMinHash *kmerminhash_difference(KmerMinHash *ptr, const KmerMinHash *other);
+
+KmerMinHash *kmerminhash_filter_abundance(KmerMinHash *ptr,
+                                          uint64_t min_abundance,
+                                          uint64_t max_abundance);
+
+KmerMinHash *kmerminhash_flatten(KmerMinHash *ptr);
+
 void kmerminhash_free(KmerMinHash *ptr);
 
 uint64_t kmerminhash_get_abund_idx(KmerMinHash *ptr, uint64_t idx);
@@ -69,14 +115,26 @@ const uint64_t *kmerminhash_get_mins(KmerMinHash *ptr);
 
 uintptr_t kmerminhash_get_mins_size(KmerMinHash *ptr);
 
+HashFunction kmerminhash_hash_function(KmerMinHash *ptr);
+
+bool kmerminhash_hp(KmerMinHash *ptr);
+
 uint64_t kmerminhash_intersection(KmerMinHash *ptr, const KmerMinHash *other);
 
 bool kmerminhash_is_protein(KmerMinHash *ptr);
 
+double kmerminhash_jaccard_ani(KmerMinHash *ptr, const KmerMinHash *other);
+
 uint32_t kmerminhash_ksize(KmerMinHash *ptr);
 
 uint64_t kmerminhash_max_hash(KmerMinHash *ptr);
 
+double kmerminhash_max_containment(KmerMinHash *ptr, const KmerMinHash *other);
+
+double kmerminhash_max_containment_ani(KmerMinHash *ptr, const KmerMinHash *other);
+
+SourmashStr kmerminhash_md5sum(KmerMinHash *ptr);
+
 void kmerminhash_merge(KmerMinHash *ptr, const KmerMinHash *other);
 
 void kmerminhash_mins_push(KmerMinHash *ptr, uint64_t val);
@@ -90,10 +148,34 @@ KmerMinHash *kmerminhash_new(uint32_t n,
 
 uint32_t kmerminhash_num(KmerMinHash *ptr);
 
+void kmerminhash_remove_hash(KmerMinHash *ptr, uint64_t h);
+
+void kmerminhash_remove_many(KmerMinHash *ptr, const uint64_t *hashes_ptr, uintptr_t insize);
+
+uint64_t kmerminhash_scaled(KmerMinHash *ptr);
+
 uint64_t kmerminhash_seed(KmerMinHash *ptr);
 
+void kmerminhash_set_dayhoff(KmerMinHash *ptr, bool dayhoff);
+
+void kmerminhash_set_hash_function(KmerMinHash *ptr, uint32_t hash_function);
+
+void kmerminhash_set_hp(KmerMinHash *ptr, bool hp);
+
+void kmerminhash_set_translation_table(KmerMinHash *ptr, uint8_t table);
+
+double kmerminhash_similarity(KmerMinHash *ptr, const KmerMinHash *other, bool ignore_abundance);
+
+KmerMinHash *kmerminhash_symmetric_difference(KmerMinHash *ptr, const KmerMinHash *other);
+
 bool kmerminhash_track_abundance(KmerMinHash *ptr);
 
+void kmerminhash_track_cardinality(KmerMinHash *ptr, uint8_t p);
+
+uint8_t kmerminhash_translation_table(KmerMinHash *ptr);
+
+KmerMinHash *kmerminhash_union(KmerMinHash *ptr, const KmerMinHash *other);
+
 bool signature_eq(Signature *ptr, Signature *other);
 
 KmerMinHash *signature_first_mh(Signature *ptr);
@@ -0,0 +1,402 @@
+//! Compact binary encoding for signatures.
+//!
+//! In JSON every hash is written as decimal text, about 20 bytes each. Here
+//! the sorted hashes of a sketch are stored as varint-encoded differences
+//! between consecutive hashes, which take 2-4 bytes for typical scaled
+//! sketches. `Signature::from_reader` detects this format from the magic
+//! header, so both encodings can be loaded by the same functions.
+//!
+//! Layout (fixed-size integers are little endian, `varint` is unsigned
+//! LEB128, `string` is a varint length followed by UTF-8 bytes):
+//!
+//! ```text
+//! magic              b"SMSG"
+//! version            u8
+//! n_signatures       varint
+//! per signature:
+//!   class, email, hash_function, license      string
+//!   filename, name                            u8 (0 if missing) + string
+//!   version                                   f64
+//!   n_sketches                                varint
+//!   per sketch:
+//!     num, ksize                              varint
+//!     seed, max_hash                          u64
+//!     molecule                                u8 (0 DNA, 1 protein, 2 dayhoff, 3 hp)
+//!     translation_table                       u8
+//!     flags                                   u8 (1: abundances, 2: HyperLogLog)
+//!     n_hashes                                varint
+//!     hashes                                  varint * n_hashes, each the difference
+//!                                             from the previous hash (the first from 0)
+//!     abundances                              varint * n_hashes
+//!     HyperLogLog                             u8 precision + 2^p registers
+//! ```
+
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use failure::Error;
+
+use crate::errors::SourmashError;
+use crate::hyperloglog::HyperLogLog;
+use crate::{HashFunction, KmerMinHash, Signature};
+
+pub const MAGIC: &[u8; 4] = b"SMSG";
+pub const VERSION: u8 = 1;
+
+const HAS_ABUNDANCES: u8 = 1;
+const HAS_HLL: u8 = 2;
+
+fn invalid(message: &str) -> Error {
+    SourmashError::InvalidBinarySignature {
+        message: message.into(),
+    }
+    .into()
+}
+
+type Rewound<R> = io::Chain<io::Cursor<Vec<u8>>, R>;
+
+/// Check if `rdr` starts with the binary format magic. The returned reader
+/// yields the whole input again, including the bytes read for the check.
+pub(crate) fn detect<R: Read>(mut rdr: R) -> io::Result<(bool, Rewound<R>)> {
+    let mut prefix = Vec::with_capacity(MAGIC.len());
+    (&mut rdr)
+        .take(MAGIC.len() as u64)
+        .read_to_end(&mut prefix)?;
+    let is_binary = prefix[..] == MAGIC[..];
+    Ok((is_binary, io::Cursor::new(prefix).chain(rdr)))
+}
+
+fn write_varint<W: Write>(wtr: &mut W, mut value: u64) -> io::Result<()> {
+    while value >= 0x80 {
+        wtr.write_u8((value as u8 & 0x7f) | 0x80)?;
+        value >>= 7;
+    }
+    wtr.write_u8(value as u8)
+}
+
+fn read_varint<R: Read>(rdr: &mut R) -> Result<u64, Error> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = rdr.read_u8()?;
+        let bits = u64::from(byte & 0x7f);
+        if shift == 63 && bits > 1 {
+            break;
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("varint overflows 64 bits"))
+}
+
+fn read_len<R: Read>(rdr: &mut R) -> Result<usize, Error> {
+    let len = read_varint(rdr)?;
+    if len > usize::MAX as u64 {
+        return Err(invalid("length too large"));
+    }
+    Ok(len as usize)
+}
+
+fn write_string<W: Write>(wtr: &mut W, s: &str) -> io::Result<()> {
+    write_varint(wtr, s.len() as u64)?;
+    wtr.write_all(s.as_bytes())
+}
+
+fn read_string<R: Read>(rdr: &mut R) -> Result<String, Error> {
+    let len = read_varint(rdr)?;
+    let mut buf = Vec::new();
+    rdr.take(len).read_to_end(&mut buf)?;
+    if buf.len() as u64 != len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(String::from_utf8(buf)?)
+}
+
+fn write_option<W: Write>(wtr: &mut W, s: &Option<String>) -> io::Result<()> {
+    match s {
+        Some(s) => {
+            wtr.write_u8(1)?;
+            write_string(wtr, s)
+        }
+        None => wtr.write_u8(0),
+    }
+}
+
+fn read_option<R: Read>(rdr: &mut R) -> Result<Option<String>, Error> {
+    match rdr.read_u8()? {
+        0 => Ok(None),
+        1 => Ok(Some(read_string(rdr)?)),
+        _ => Err(invalid("invalid optional field")),
+    }
+}
+
+fn write_sketch<W: Write>(wtr: &mut W, mh: &KmerMinHash) -> io::Result<()> {
+    write_varint(wtr, u64::from(mh.num))?;
+    write_varint(wtr, u64::from(mh.ksize))?;
+    wtr.write_u64::<LittleEndian>(mh.seed)?;
+    wtr.write_u64::<LittleEndian>(mh.max_hash)?;
+
+    let molecule = match (mh.is_protein, mh.dayhoff, mh.hp) {
+        (false, _, _) => 0,
+        (true, true, _) => 2,
+        (true, false, true) => 3,
+        (true, false, false) => 1,
+    };
+    wtr.write_u8(molecule)?;
+    wtr.write_u8(mh.translation_table)?;
+
+    let mut flags = 0;
+    if mh.abunds.is_some() {
+        flags |= HAS_ABUNDANCES;
+    }
+    if mh.hll.is_some() {
+        flags |= HAS_HLL;
+    }
+    wtr.write_u8(flags)?;
+
+    write_varint(wtr, mh.mins.len() as u64)?;
+    let mut previous = 0;
+    for &hash in &mh.mins {
+        write_varint(wtr, hash.wrapping_sub(previous))?;
+        previous = hash;
+    }
+
+    if let Some(abunds) = &mh.abunds {
+        for &abund in abunds {
+            write_varint(wtr, abund)?;
+        }
+    }
+
+    if let Some(hll) = &mh.hll {
+        wtr.write_u8(hll.precision())?;
+        wtr.write_all(hll.registers())?;
+    }
+    Ok(())
+}
+
+fn read_sketch<R: Read>(rdr: &mut R, hash_function: HashFunction) -> Result<KmerMinHash, Error> {
+    let num = read_varint(rdr)?;
+    let ksize = read_varint(rdr)?;
+    if num > u64::from(u32::MAX) || ksize > u64::from(u32::MAX) {
+        return Err(invalid("num or ksize too large"));
+    }
+    let seed = rdr.read_u64::<LittleEndian>()?;
+    let max_hash = rdr.read_u64::<LittleEndian>()?;
+
+    let (is_protein, dayhoff, hp) = match rdr.read_u8()? {
+        0 => (false, false, false),
+        1 => (true, false, false),
+        2 => (true, true, false),
+        3 => (true, false, true),
+        _ => return Err(invalid("unknown molecule type")),
+    };
+    let translation_table = rdr.read_u8()?;
+    let flags = rdr.read_u8()?;
+    if flags & !(HAS_ABUNDANCES | HAS_HLL) != 0 {
+        return Err(invalid("unknown sketch flags"));
+    }
+
+    // don't trust the stored counts for preallocation, the data might be
+    // truncated
+    let n_hashes = read_len(rdr)?;
+    let mut mins = Vec::new();
+    let mut previous = 0u64;
+    for _ in 0..n_hashes {
+        let hash = previous
+            .checked_add(read_varint(rdr)?)
+            .ok_or_else(|| invalid("hash overflows 64 bits"))?;
+        mins.push(hash);
+        previous = hash;
+    }
+
+    let abunds = if flags & HAS_ABUNDANCES != 0 {
+        let mut abunds = Vec::new();
+        for _ in 0..n_hashes {
+            abunds.push(read_varint(rdr)?);
+        }
+        Some(abunds)
+    } else {
+        None
+    };
+
+    let hll = if flags & HAS_HLL != 0 {
+        let mut hll = HyperLogLog::new(rdr.read_u8()?)?;
+        rdr.read_exact(hll.registers_mut())?;
+        Some(hll)
+    } else {
+        None
+    };
+
+    let mh = KmerMinHash {
+        num: num as u32,
+        ksize: ksize as u32,
+        is_protein,
+        dayhoff,
+        hp,
+        translation_table,
+        hash_function,
+        seed,
+        max_hash,
+        mins,
+        abunds,
+        hll,
+    };
+    mh.validate()?;
+    Ok(mh)
+}
+
+/// Write `sigs` in the binary format.
+pub fn write_signatures<W: Write>(wtr: &mut W, sigs: &[Signature]) -> Result<(), Error> {
+    wtr.write_all(MAGIC)?;
+    wtr.write_u8(VERSION)?;
+    write_varint(wtr, sigs.len() as u64)?;
+
+    for sig in sigs {
+        write_string(wtr, &sig.class)?;
+        write_string(wtr, &sig.email)?;
+        write_string(wtr, sig.hash_function_name()?)?;
+        write_string(wtr, &sig.license)?;
+        write_option(wtr, &sig.filename)?;
+        write_option(wtr, &sig.name)?;
+        wtr.write_f64::<LittleEndian>(sig.version)?;
+
+        write_varint(wtr, sig.signatures.len() as u64)?;
+        for mh in &sig.signatures {
+            write_sketch(wtr, mh)?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Read signatures in the binary format, starting from the magic header.
+/// Sketches are checked with `KmerMinHash::validate`.
+pub fn read_signatures<R: Read>(rdr: &mut R) -> Result<Vec<Signature>, Error> {
+    let n_sigs = read_header(rdr)?;
+    let mut sigs = Vec::new();
+    for _ in 0..n_sigs {
+        sigs.push(read_signature(rdr)?);
+    }
+    Ok(sigs)
+}
+
+/// Read the magic header and version, returning the number of signatures
+/// that follow.
+pub(crate) fn read_header<R: Read>(rdr: &mut R) -> Result<usize, Error> {
+    let mut magic = [0u8; 4];
+    rdr.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("missing magic header"));
+    }
+    let version = rdr.read_u8()?;
+    if version != VERSION {
+        return Err(invalid(&format!("unsupported version {}", version)));
+    }
+    read_len(rdr)
+}
+
+pub(crate) fn read_signature<R: Read>(rdr: &mut R) -> Result<Signature, Error> {
+    let class = read_string(rdr)?;
+    let email = read_string(rdr)?;
+    let hash_function = read_string(rdr)?;
+    let license = read_string(rdr)?;
+    let filename = read_option(rdr)?;
+    let name = read_option(rdr)?;
+    let version = rdr.read_f64::<LittleEndian>()?;
+
+    let function: HashFunction = hash_function.parse()?;
+    let n_sketches = read_len(rdr)?;
+    let mut signatures = Vec::new();
+    for _ in 0..n_sketches {
+        signatures.push(read_sketch(rdr, function)?);
+    }
+
+    Ok(Signature {
+        class,
+        email,
+        hash_function,
+        filename,
+        name,
+        license,
+        signatures,
+        version,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::varied_sigs;
+
+    // Signature equality only looks at the first sketch
+    fn assert_same(loaded: &[Signature], sigs: &[Signature]) {
+        assert_eq!(loaded, sigs);
+        for (l, s) in loaded.iter().zip(sigs) {
+            assert_eq!(l.signatures, s.signatures);
+            assert_eq!(l.license, s.license);
+            assert_eq!(l.version, s.version);
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let sigs = varied_sigs();
+        assert!(sigs[0].signatures[0].size() > 0);
+
+        let mut buf = Vec::new();
+        write_signatures(&mut buf, &sigs).unwrap();
+        assert_eq!(&buf[..4], MAGIC);
+        assert_same(&read_signatures(&mut &buf[..]).unwrap(), &sigs);
+
+        // both formats load through Signature::from_reader
+        let json = serde_json::to_vec(&sigs).unwrap();
+        assert!(buf.len() < json.len());
+        assert_same(&Signature::from_reader(&mut &buf[..]).unwrap(), &sigs);
+        assert_same(&Signature::from_reader(&mut &json[..]).unwrap(), &sigs);
+        assert_same(
+            &Signature::from_reader_checked(&mut &buf[..]).unwrap(),
+            &sigs,
+        );
+
+        let mut empty = Vec::new();
+        write_signatures(&mut empty, &[]).unwrap();
+        assert!(Signature::from_reader(&mut &empty[..]).unwrap().is_empty());
+        assert!(Signature::from_reader(&mut &b"[]"[..]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_input() {
+        let mut buf = Vec::new();
+        write_signatures(&mut buf, &varied_sigs()).unwrap();
+
+        for len in &[0, 5, buf.len() / 2, buf.len() - 1] {
+            assert!(read_signatures(&mut &buf[..*len]).is_err());
+        }
+
+        let mut version = buf.clone();
+        version[4] = 99;
+        let err = read_signatures(&mut &version[..]).unwrap_err();
+        assert_error_code!(err, InvalidBinarySignature);
+
+        // a repeated hash (difference of 0) is rejected by validation
+        let mut mh = KmerMinHash::new(0, 21, false, 42, 0, false);
+        mh.mins = vec![5, 10];
+        let mut sig = Signature::default();
+        sig.signatures = vec![mh];
+        let mut dup = Vec::new();
+        write_signatures(&mut dup, &[sig]).unwrap();
+        let last = dup.len() - 1;
+        assert_eq!(dup[last], 5);
+        dup[last] = 0;
+        let err = Signature::from_reader(&mut &dup[..]).unwrap_err();
+        assert_error_code!(err, UnsortedHashes);
+
+        let mut overflow = vec![0xff; 10];
+        overflow.push(0x01);
+        assert!(read_varint(&mut &overflow[..]).is_err());
+        let mut max = Vec::new();
+        write_varint(&mut max, u64::MAX).unwrap();
+        assert_eq!(read_varint(&mut &max[..]).unwrap(), u64::MAX);
+    }
+}
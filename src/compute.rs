@@ -0,0 +1,334 @@
+//! Building signatures with several sketches from a single pass over the
+//! input sequences.
+
+use std::collections::BTreeMap;
+
+use failure::Error;
+
+use crate::errors::SourmashError;
+use crate::fastx::{add_records_with, FastxReader, Record};
+use crate::hyperloglog::HyperLogLog;
+use crate::{for_each_canonical_kmer, max_hash_for_scaled, HashFunction, KmerMinHash, Signature};
+
+/// Parameters for one sketch in a signature.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SketchParams {
+    /// K-mer size. For protein sketches (and the reduced alphabets) this is
+    /// three times the amino acid k-mer size, same as in `KmerMinHash`.
+    pub ksize: u32,
+    /// One of `dna`, `protein`, `dayhoff` or `hp`.
+    pub moltype: String,
+    pub num: u32,
+    pub scaled: u64,
+    pub track_abundance: bool,
+    /// `HashFunction::Murmur64` unless changed. All sketches in a signature
+    /// must use the same one.
+    pub hash_function: HashFunction,
+}
+
+impl SketchParams {
+    pub fn new(
+        ksize: u32,
+        moltype: &str,
+        num: u32,
+        scaled: u64,
+        track_abundance: bool,
+    ) -> SketchParams {
+        SketchParams {
+            ksize,
+            moltype: moltype.into(),
+            num,
+            scaled,
+            track_abundance,
+            hash_function: HashFunction::Murmur64,
+        }
+    }
+
+    /// An empty sketch with these parameters.
+    pub fn to_minhash(&self, seed: u64) -> Result<KmerMinHash, Error> {
+        let (is_protein, dayhoff, hp) = match self.moltype.to_lowercase().as_str() {
+            "dna" => (false, false, false),
+            "protein" => (true, false, false),
+            "dayhoff" => (true, true, false),
+            "hp" => (true, false, true),
+            _ => {
+                return Err(SourmashError::InvalidMolecule {
+                    molecule: self.moltype.clone(),
+                }
+                .into());
+            }
+        };
+
+        let max_hash = if self.scaled == 0 {
+            0
+        } else {
+            max_hash_for_scaled(self.scaled)
+        };
+
+        let mut mh = KmerMinHash::new(
+            self.num,
+            self.ksize,
+            is_protein,
+            seed,
+            max_hash,
+            self.track_abundance,
+        );
+        mh.dayhoff = dayhoff;
+        mh.hp = hp;
+        mh.hash_function = self.hash_function;
+        Ok(mh)
+    }
+}
+
+/// Fills all the sketches of a signature from one pass over the input.
+///
+/// DNA sketches with the same k-mer size share the k-mer extraction and
+/// hashing, so adding more `num`/`scaled` variants is cheap. Protein sketches
+/// still translate the input once per sketch.
+#[derive(Clone)]
+pub struct SignatureBuilder {
+    sig: Signature,
+    /// Indices of the DNA sketches, grouped by k-mer size.
+    dna: BTreeMap<u32, Vec<usize>>,
+    protein: Vec<usize>,
+}
+
+impl SignatureBuilder {
+    pub fn new(params: &[SketchParams], seed: u64) -> Result<SignatureBuilder, Error> {
+        let mut sig = Signature::default();
+        let mut dna: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+        let mut protein = Vec::new();
+
+        if let Some(first) = params.first() {
+            if params
+                .iter()
+                .any(|p| p.hash_function != first.hash_function)
+            {
+                return Err(SourmashError::MismatchHashFunction.into());
+            }
+            sig.hash_function = first.hash_function.as_str().into();
+        }
+
+        for (i, p) in params.iter().enumerate() {
+            let mh = p.to_minhash(seed)?;
+            if mh.is_protein {
+                protein.push(i);
+            } else {
+                dna.entry(mh.ksize).or_default().push(i);
+            }
+            sig.signatures.push(mh);
+        }
+
+        Ok(SignatureBuilder { sig, dna, protein })
+    }
+
+    pub fn name(&mut self, name: &str) -> &mut Self {
+        self.sig.name = Some(name.into());
+        self
+    }
+
+    pub fn filename(&mut self, filename: &str) -> &mut Self {
+        self.sig.filename = Some(filename.into());
+        self
+    }
+
+    /// Add a DNA sequence to all sketches. `force` works the same as in
+    /// `KmerMinHash::add_sequence`.
+    pub fn add_sequence(&mut self, seq: &[u8], force: bool) -> Result<(), Error> {
+        let signatures = &mut self.sig.signatures;
+
+        for (&ksize, group) in &self.dna {
+            // all sketches in the signature share hash function and seed
+            let first = &signatures[group[0]];
+            let (hash_function, seed) = (first.hash_function, first.seed);
+
+            for_each_canonical_kmer(seq, ksize as usize, force, |_, kmer| {
+                let hash = hash_function.hash(kmer, seed);
+                for &i in group {
+                    signatures[i].add_hash(hash);
+                }
+            })?;
+        }
+
+        for &i in &self.protein {
+            signatures[i].add_sequence(seq, force)?;
+        }
+        Ok(())
+    }
+
+    /// Add every record, returning how many were added. See
+    /// `fastx::add_records`.
+    pub fn add_records<I>(&mut self, records: I, force: bool) -> Result<usize, Error>
+    where
+        I: IntoIterator<Item = Result<Record, Error>>,
+    {
+        add_records_with(records, force, |record| {
+            self.add_sequence(&record.seq, force)
+        })
+    }
+
+    /// Add all records in a FASTA/FASTQ file. The path is used as `filename`
+    /// if it wasn't set before.
+    pub fn add_path(&mut self, path: &str, force: bool) -> Result<usize, Error> {
+        if self.sig.filename.is_none() {
+            self.filename(path);
+        }
+        self.add_records(FastxReader::from_path(path)?, force)
+    }
+
+    /// Singleton mode: one signature per record, named after the record
+    /// header. Each signature starts from the (empty) sketches and filename of
+    /// this builder, which is left unchanged.
+    pub fn singletons<I>(&self, records: I, force: bool) -> Result<Vec<Signature>, Error>
+    where
+        I: IntoIterator<Item = Result<Record, Error>>,
+    {
+        let mut template = self.clone();
+        for mh in &mut template.sig.signatures {
+            let hll = mh.hll.as_ref().map(HyperLogLog::copy_empty);
+            *mh = mh.copy_empty();
+            // each record gets its own estimate
+            mh.hll = hll;
+        }
+
+        let mut sigs = Vec::new();
+        add_records_with(records, force, |record| {
+            let mut builder = template.clone();
+            builder.name(&record.name);
+            builder.add_sequence(&record.seq, force)?;
+            sigs.push(builder.build());
+            Ok(())
+        })?;
+        Ok(sigs)
+    }
+
+    /// Singleton signatures for all records in a FASTA/FASTQ file, with the
+    /// path as `filename`.
+    pub fn singletons_from_path(&self, path: &str, force: bool) -> Result<Vec<Signature>, Error> {
+        let mut template = self.clone();
+        template.filename(path);
+        template.singletons(FastxReader::from_path(path)?, force)
+    }
+
+    pub fn build(self) -> Signature {
+        self.sig
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn single_pass_matches_sketches() {
+        let params = vec![
+            SketchParams::new(21, "dna", 0, 2, true),
+            SketchParams::new(21, "dna", 50, 0, false),
+            SketchParams::new(31, "dna", 0, 1, false),
+            SketchParams::new(30, "protein", 0, 2, false),
+            SketchParams::new(30, "dayhoff", 100, 0, true),
+        ];
+
+        let data = b">a\nACGTTGCAACGGCATCGATCGACTAGCTACGATCGACTGACTAGCT\n>b\nGGCATCGATCGACTAGCTACGACCCATGACTTT\n";
+
+        let mut builder = SignatureBuilder::new(&params, 42).unwrap();
+        builder.name("test");
+        assert_eq!(
+            builder
+                .add_records(FastxReader::new(&data[..]), false)
+                .unwrap(),
+            2
+        );
+        let sig = builder.build();
+
+        assert_eq!(sig.name, Some("test".into()));
+        assert_eq!(sig.signatures.len(), params.len());
+
+        let seqs = [
+            &b"ACGTTGCAACGGCATCGATCGACTAGCTACGATCGACTGACTAGCT"[..],
+            &b"GGCATCGATCGACTAGCTACGACCCATGACTTT"[..],
+        ];
+        for (p, mh) in params.iter().zip(&sig.signatures) {
+            let mut expected = p.to_minhash(42).unwrap();
+            for seq in &seqs {
+                expected.add_sequence(seq, false).unwrap();
+            }
+            assert_eq!(mh, &expected);
+            assert!(mh.size() > 0);
+        }
+        assert!(sig.signatures[4].dayhoff);
+    }
+
+    #[test]
+    fn singletons() {
+        let params = vec![
+            SketchParams::new(5, "dna", 0, 1, false),
+            SketchParams::new(9, "protein", 0, 1, false),
+        ];
+        let mut builder = SignatureBuilder::new(&params, 42).unwrap();
+        builder.filename("genes.fa");
+
+        let data = b">gene1 first gene\nACGTTGCAACGG\n>gene2\nGGCATCGAT\n>bad\nACGNNTGCA\n";
+        assert!(builder
+            .singletons(FastxReader::new(&data[..]), false)
+            .is_err());
+
+        let sigs = builder
+            .singletons(FastxReader::new(&data[..]), true)
+            .unwrap();
+        // with force invalid k-mers are skipped, but the record is kept
+        assert_eq!(sigs.len(), 3);
+        assert_eq!(sigs[0].name, Some("gene1 first gene".into()));
+        assert_eq!(sigs[1].name, Some("gene2".into()));
+        assert_eq!(sigs[2].name, Some("bad".into()));
+
+        let mut expected = params[0].to_minhash(42).unwrap();
+        expected.add_sequence(b"GGCATCGAT", false).unwrap();
+        for sig in &sigs {
+            assert_eq!(sig.filename, Some("genes.fa".into()));
+            assert_eq!(sig.signatures.len(), 2);
+        }
+        assert_eq!(sigs[1].signatures[0], expected);
+
+        // the builder itself is not modified
+        assert_eq!(builder.build().signatures[0].size(), 0);
+    }
+
+    #[test]
+    fn hash_functions() {
+        let mut params = vec![
+            SketchParams::new(21, "dna", 0, 1, false),
+            SketchParams::new(9, "protein", 0, 1, false),
+        ];
+        for p in &mut params {
+            p.hash_function = HashFunction::XxHash64;
+        }
+
+        let mut builder = SignatureBuilder::new(&params, 42).unwrap();
+        builder
+            .add_sequence(b"ACGTTGCAACGGCATCGATCGACTAG", false)
+            .unwrap();
+        let sig = builder.build();
+        assert_eq!(sig.hash_function, "0.xxhash64");
+        for (p, mh) in params.iter().zip(&sig.signatures) {
+            let mut expected = p.to_minhash(42).unwrap();
+            assert_eq!(expected.hash_function, HashFunction::XxHash64);
+            expected
+                .add_sequence(b"ACGTTGCAACGGCATCGATCGACTAG", false)
+                .unwrap();
+            assert_eq!(mh, &expected);
+        }
+
+        params[1].hash_function = HashFunction::Murmur64;
+        let err = SignatureBuilder::new(&params, 42).err().unwrap();
+        assert_error_code!(err, MismatchHashFunction);
+    }
+
+    #[test]
+    fn invalid_moltype() {
+        let err = SignatureBuilder::new(&[SketchParams::new(21, "rna", 0, 10, false)], 42)
+            .err()
+            .unwrap();
+        assert_error_code!(err, InvalidMolecule);
+    }
+}
@@ -18,11 +18,76 @@ pub enum SourmashError {
     #[fail(display = "mismatch in seed; comparison fail")]
     MismatchSeed,
 
+    #[fail(display = "mismatch in hash function; comparison fail")]
+    MismatchHashFunction,
+
+    #[fail(display = "unknown hash function: {}", function)]
+    InvalidHashFunction { function: String },
+
+    #[fail(display = "unknown molecule type: {}", molecule)]
+    InvalidMolecule { molecule: String },
+
+    #[fail(display = "HyperLogLog precision must be between 4 and 18, got {}", p)]
+    InvalidHllPrecision { p: u8 },
+
+    #[fail(display = "mismatch in HyperLogLog precision; merge fail")]
+    MismatchHllPrecision,
+
+    #[fail(display = "can only set {} if the sketch is empty", message)]
+    NonEmptyMinHash { message: String },
+
+    #[fail(display = "invalid downsampling: {}", message)]
+    InvalidDownsample { message: String },
+
+    #[fail(display = "sketches need abundance tracking for this operation")]
+    NeedsAbundanceTracking,
+
+    #[fail(display = "unknown translation table: {}", table)]
+    InvalidTranslationTable { table: u8 },
+
     #[fail(display = "invalid DNA character in input k-mer: {}", message)]
     InvalidDNA { message: String },
 
     #[fail(display = "invalid protein character in input: {}", message)]
     InvalidProt { message: String },
+
+    #[fail(display = "invalid sequence record: {}", message)]
+    InvalidRecord { message: String },
+
+    #[fail(display = "invalid picklist: {}", message)]
+    InvalidPicklist { message: String },
+
+    #[fail(display = "md5sum mismatch: stored {}, computed {}", stored, computed)]
+    InvalidMd5sum { stored: String, computed: String },
+
+    #[fail(display = "hash at position {} is not sorted or unique", position)]
+    UnsortedHashes { position: usize },
+
+    #[fail(display = "found {} abundances for {} hashes", abundances, hashes)]
+    InvalidAbundances { hashes: usize, abundances: usize },
+
+    #[fail(display = "sketch has {} hashes, more than num ({})", hashes, num)]
+    InvalidNum { num: u32, hashes: usize },
+
+    #[fail(display = "hash {} is above max_hash ({})", hash, max_hash)]
+    InvalidMaxHash { hash: u64, max_hash: u64 },
+
+    #[fail(display = "invalid binary signature: {}", message)]
+    InvalidBinarySignature { message: String },
+}
+
+/// Panic unless `err` maps to `SourmashErrorCode::$code`. Errors can't be
+/// compared directly, so tests check their codes.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! assert_error_code {
+    ($err:expr, $code:ident) => {{
+        let err = &$err;
+        match $crate::errors::SourmashErrorCode::from_error(err) {
+            $crate::errors::SourmashErrorCode::$code => (),
+            _ => panic!("expected a {} error, got: {}", stringify!($code), err),
+        }
+    }};
 }
 
 #[repr(u32)]
@@ -39,9 +104,27 @@ pub enum SourmashErrorCode {
     MismatchDNAProt = 1_02,
     MismatchMaxHash = 1_03,
     MismatchSeed = 1_04,
+    InvalidDownsample = 1_05,
+    NeedsAbundanceTracking = 1_06,
+    InvalidTranslationTable = 1_07,
+    MismatchHashFunction = 1_08,
+    InvalidHashFunction = 1_09,
+    InvalidMolecule = 1_10,
+    InvalidHllPrecision = 1_11,
+    MismatchHllPrecision = 1_12,
+    NonEmptyMinHash = 1_13,
     // Input sequence errors
     InvalidDNA = 11_01,
     InvalidProt = 11_02,
+    InvalidRecord = 11_03,
+    InvalidPicklist = 11_04,
+    // Signature content errors
+    InvalidMd5sum = 12_01,
+    UnsortedHashes = 12_02,
+    InvalidAbundances = 12_03,
+    InvalidNum = 12_04,
+    InvalidMaxHash = 12_05,
+    InvalidBinarySignature = 12_06,
     // external errors
     Io = 100_001,
     Utf8Error = 100_002,
@@ -64,8 +147,35 @@ impl SourmashErrorCode {
                     SourmashError::MismatchDNAProt => SourmashErrorCode::MismatchDNAProt,
                     SourmashError::MismatchMaxHash => SourmashErrorCode::MismatchMaxHash,
                     SourmashError::MismatchSeed => SourmashErrorCode::MismatchSeed,
+                    SourmashError::MismatchHashFunction => SourmashErrorCode::MismatchHashFunction,
+                    SourmashError::InvalidHashFunction { .. } => {
+                        SourmashErrorCode::InvalidHashFunction
+                    }
+                    SourmashError::InvalidMolecule { .. } => SourmashErrorCode::InvalidMolecule,
+                    SourmashError::InvalidHllPrecision { .. } => {
+                        SourmashErrorCode::InvalidHllPrecision
+                    }
+                    SourmashError::MismatchHllPrecision => SourmashErrorCode::MismatchHllPrecision,
+                    SourmashError::NonEmptyMinHash { .. } => SourmashErrorCode::NonEmptyMinHash,
+                    SourmashError::InvalidDownsample { .. } => SourmashErrorCode::InvalidDownsample,
+                    SourmashError::NeedsAbundanceTracking => {
+                        SourmashErrorCode::NeedsAbundanceTracking
+                    }
+                    SourmashError::InvalidTranslationTable { .. } => {
+                        SourmashErrorCode::InvalidTranslationTable
+                    }
                     SourmashError::InvalidDNA { .. } => SourmashErrorCode::InvalidDNA,
                     SourmashError::InvalidProt { .. } => SourmashErrorCode::InvalidProt,
+                    SourmashError::InvalidRecord { .. } => SourmashErrorCode::InvalidRecord,
+                    SourmashError::InvalidPicklist { .. } => SourmashErrorCode::InvalidPicklist,
+                    SourmashError::InvalidMd5sum { .. } => SourmashErrorCode::InvalidMd5sum,
+                    SourmashError::UnsortedHashes { .. } => SourmashErrorCode::UnsortedHashes,
+                    SourmashError::InvalidAbundances { .. } => SourmashErrorCode::InvalidAbundances,
+                    SourmashError::InvalidNum { .. } => SourmashErrorCode::InvalidNum,
+                    SourmashError::InvalidMaxHash { .. } => SourmashErrorCode::InvalidMaxHash,
+                    SourmashError::InvalidBinarySignature { .. } => {
+                        SourmashErrorCode::InvalidBinarySignature
+                    }
                 };
             }
         }
@@ -0,0 +1,485 @@
+use std::io::{self, BufRead, BufReader};
+
+use failure::{Error, ResultExt};
+use log::warn;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::errors::SourmashError;
+use crate::file::get_input;
+#[cfg(feature = "parallel")]
+use crate::hyperloglog::HyperLogLog;
+use crate::KmerMinHash;
+
+/// A sequence record from a FASTA or FASTQ file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// Header line, without the leading `>` or `@`.
+    pub name: String,
+    pub seq: Vec<u8>,
+    /// Quality line, only available for FASTQ records.
+    pub qual: Option<Vec<u8>>,
+}
+
+/// Reads FASTA and FASTQ records. The format is detected from the first
+/// character of each record header.
+pub struct FastxReader<R> {
+    reader: R,
+    line: Vec<u8>,
+    line_number: usize,
+    next_header: Option<Vec<u8>>,
+    finished: bool,
+}
+
+impl FastxReader<BufReader<Box<dyn io::Read>>> {
+    /// Open a file (or `-` for stdin), decompressing gzip, bzip2 and xz
+    /// inputs transparently.
+    pub fn from_path(path: &str) -> Result<Self, Error> {
+        let (input, _) = get_input(path).context(format!("can't open {}", path))?;
+        Ok(FastxReader::new(BufReader::new(input)))
+    }
+}
+
+impl<R: BufRead> FastxReader<R> {
+    pub fn new(reader: R) -> FastxReader<R> {
+        FastxReader {
+            reader,
+            line: Vec::new(),
+            line_number: 0,
+            next_header: None,
+            finished: false,
+        }
+    }
+
+    fn read_line(&mut self) -> Result<bool, Error> {
+        self.line.clear();
+        let read = match self.reader.read_until(b'\n', &mut self.line) {
+            Ok(read) => read,
+            Err(e) => {
+                // IO errors are not recoverable, stop reading
+                self.finished = true;
+                return Err(e.into());
+            }
+        };
+        self.line_number += 1;
+
+        while let Some(b'\n') | Some(b'\r') = self.line.last() {
+            self.line.pop();
+        }
+        Ok(read > 0)
+    }
+
+    fn invalid(&self, message: &str) -> Error {
+        SourmashError::InvalidRecord {
+            message: format!("{} (line {})", message, self.line_number),
+        }
+        .into()
+    }
+
+    fn next_record(&mut self) -> Result<Option<Record>, Error> {
+        let header = match self.next_header.take() {
+            Some(header) => header,
+            None => loop {
+                if !self.read_line()? {
+                    return Ok(None);
+                }
+                if !self.line.is_empty() {
+                    break self.line.clone();
+                }
+            },
+        };
+
+        let name = String::from_utf8_lossy(&header[1..]).into_owned();
+        match header[0] {
+            b'>' => self.fasta_record(name).map(Some),
+            b'@' => self.fastq_record(name).map(Some),
+            _ => Err(self.invalid("expected a header starting with '>' or '@'")),
+        }
+    }
+
+    fn fasta_record(&mut self, name: String) -> Result<Record, Error> {
+        let mut seq = Vec::new();
+        while self.read_line()? {
+            if self.line.first() == Some(&b'>') {
+                self.next_header = Some(self.line.clone());
+                break;
+            }
+            seq.extend_from_slice(&self.line);
+        }
+
+        Ok(Record {
+            name,
+            seq,
+            qual: None,
+        })
+    }
+
+    fn fastq_record(&mut self, name: String) -> Result<Record, Error> {
+        if !self.read_line()? {
+            return Err(self.invalid("truncated FASTQ record, missing sequence"));
+        }
+        let seq = self.line.clone();
+
+        if !self.read_line()? || self.line.first() != Some(&b'+') {
+            return Err(self.invalid("FASTQ separator line must start with '+'"));
+        }
+
+        if !self.read_line()? {
+            return Err(self.invalid("truncated FASTQ record, missing quality"));
+        }
+        if self.line.len() != seq.len() {
+            return Err(self.invalid("FASTQ quality and sequence lengths differ"));
+        }
+
+        Ok(Record {
+            name,
+            seq,
+            qual: Some(self.line.clone()),
+        })
+    }
+}
+
+impl<R: BufRead> Iterator for FastxReader<R> {
+    type Item = Result<Record, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        match self.next_record() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Add every record to all the `sketches`, returning how many records were
+/// added.
+///
+/// `force` is passed to `KmerMinHash::add_sequence`, and also allows skipping
+/// (with a warning) records that can't be parsed.
+pub fn add_records<I>(records: I, sketches: &mut [KmerMinHash], force: bool) -> Result<usize, Error>
+where
+    I: IntoIterator<Item = Result<Record, Error>>,
+{
+    add_records_with(records, force, |record| {
+        for mh in sketches.iter_mut() {
+            mh.add_sequence(&record.seq, force)?;
+        }
+        Ok(())
+    })
+}
+
+/// Same as `add_records`, but for amino acid records (added with
+/// `KmerMinHash::add_protein`). With `force`, records with invalid residues
+/// are skipped.
+pub fn add_protein_records<I>(
+    records: I,
+    sketches: &mut [KmerMinHash],
+    force: bool,
+) -> Result<usize, Error>
+where
+    I: IntoIterator<Item = Result<Record, Error>>,
+{
+    add_records_with(records, force, |record| {
+        for mh in sketches.iter_mut() {
+            mh.add_protein(&record.seq)?;
+        }
+        Ok(())
+    })
+}
+
+/// Call `add` for every record, returning how many records were added. With
+/// `force`, records that can't be parsed or added are skipped with a warning.
+pub(crate) fn add_records_with<I, F>(records: I, force: bool, mut add: F) -> Result<usize, Error>
+where
+    I: IntoIterator<Item = Result<Record, Error>>,
+    F: FnMut(&Record) -> Result<(), Error>,
+{
+    let mut added = 0;
+    for record in records {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                if force {
+                    warn!("skipping record: {}", e);
+                    continue;
+                }
+                return Err(e);
+            }
+        };
+
+        if let Err(e) = add(&record) {
+            if force {
+                warn!("skipping record {}: {}", record.name, e);
+                continue;
+            }
+            return Err(e.context(format!("error in record {}", record.name)).into());
+        }
+        added += 1;
+    }
+    Ok(added)
+}
+
+/// Same as `add_records`, but splitting the records in chunks of
+/// `chunk_size` and sketching each chunk in parallel. Every chunk is added to
+/// empty copies of `sketches`, which are then merged back in record order, so
+/// the result is the same as sketching sequentially (abundances included).
+///
+/// If a record fails (and `force` is not set), the error for the earliest
+/// failing record is returned.
+#[cfg(feature = "parallel")]
+pub fn add_records_parallel<I>(
+    records: I,
+    sketches: &mut [KmerMinHash],
+    force: bool,
+    chunk_size: usize,
+) -> Result<usize, Error>
+where
+    I: IntoIterator<Item = Result<Record, Error>>,
+{
+    add_records_parallel_with(records, sketches, force, chunk_size, |mh, seq| {
+        mh.add_sequence(seq, force)
+    })
+}
+
+/// Parallel version of `add_protein_records`, see `add_records_parallel`.
+#[cfg(feature = "parallel")]
+pub fn add_protein_records_parallel<I>(
+    records: I,
+    sketches: &mut [KmerMinHash],
+    force: bool,
+    chunk_size: usize,
+) -> Result<usize, Error>
+where
+    I: IntoIterator<Item = Result<Record, Error>>,
+{
+    add_records_parallel_with(records, sketches, force, chunk_size, |mh, seq| {
+        mh.add_protein(seq)
+    })
+}
+
+#[cfg(feature = "parallel")]
+fn add_records_parallel_with<I, F>(
+    records: I,
+    sketches: &mut [KmerMinHash],
+    force: bool,
+    chunk_size: usize,
+    add: F,
+) -> Result<usize, Error>
+where
+    I: IntoIterator<Item = Result<Record, Error>>,
+    F: Fn(&mut KmerMinHash, &[u8]) -> Result<(), Error> + Sync,
+{
+    let chunk_size = chunk_size.max(1);
+    // keep a few chunks per thread in memory at a time
+    let batch_size = rayon::current_num_threads() * 4;
+
+    let mut records = records.into_iter().peekable();
+    let mut added = 0;
+    while records.peek().is_some() {
+        let mut batch = Vec::with_capacity(batch_size);
+        while batch.len() < batch_size && records.peek().is_some() {
+            batch.push(records.by_ref().take(chunk_size).collect::<Vec<_>>());
+        }
+
+        let chunks: Vec<_> = batch
+            .into_par_iter()
+            .map(|chunk| {
+                // chunks track the cardinality too, so it can be merged back
+                let mut partial: Vec<KmerMinHash> = sketches
+                    .iter()
+                    .map(|mh| {
+                        let mut empty = mh.copy_empty();
+                        empty.hll = mh.hll.as_ref().map(HyperLogLog::copy_empty);
+                        empty
+                    })
+                    .collect();
+                add_records_with(chunk, force, |record| {
+                    for mh in partial.iter_mut() {
+                        add(mh, &record.seq)?;
+                    }
+                    Ok(())
+                })
+                .map(|n| (n, partial))
+            })
+            .collect();
+
+        for chunk in chunks {
+            let (n, partial) = chunk?;
+            for (mh, other) in sketches.iter_mut().zip(&partial) {
+                mh.merge(other)?;
+            }
+            added += n;
+        }
+    }
+    Ok(added)
+}
+
+/// Add all records in a FASTA/FASTQ file (possibly compressed) to `sketches`.
+pub fn add_path(path: &str, sketches: &mut [KmerMinHash], force: bool) -> Result<usize, Error> {
+    add_records(FastxReader::from_path(path)?, sketches, force)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::io::Write;
+
+    use super::*;
+    #[cfg(feature = "parallel")]
+    use crate::max_hash_for_scaled;
+
+    #[test]
+    fn read_fasta_and_fastq() {
+        let data = b">seq1 first\nACGT\nacgt\n\n>seq2\r\nTTTT\r\n";
+        let records: Vec<Record> = FastxReader::new(&data[..])
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].name, "seq1 first");
+        assert_eq!(records[0].seq, b"ACGTacgt".to_vec());
+        assert_eq!(records[1].seq, b"TTTT".to_vec());
+        assert_eq!(records[1].qual, None);
+
+        let data = b"@read1\nACGTA\n+\nIIIII\n@read2\nAC\n+read2\nII";
+        let records: Vec<Record> = FastxReader::new(&data[..])
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].name, "read1");
+        assert_eq!(records[0].qual, Some(b"IIIII".to_vec()));
+        assert_eq!(records[1].seq, b"AC".to_vec());
+    }
+
+    #[test]
+    fn invalid_records() {
+        let mut records = FastxReader::new(&b"@read1\nACGT\n+\nIII\n>ok\nAC\n"[..]);
+        assert!(records.next().unwrap().is_err());
+
+        let mut mh = KmerMinHash::new(0, 2, false, 42, 0, false);
+        let reader = FastxReader::new(&b"@read1\nACGT\n+\nIII\n>ok\nAC\n"[..]);
+        assert!(add_records(reader, &mut [mh.clone()], false).is_err());
+
+        let reader = FastxReader::new(&b"@read1\nACGT\n+\nIII\n>ok\nAC\n"[..]);
+        assert_eq!(
+            add_records(reader, std::slice::from_mut(&mut mh), true).unwrap(),
+            1
+        );
+        assert_eq!(mh.size(), 1);
+    }
+
+    #[test]
+    fn add_records_reports_record() {
+        let mut sketches = vec![KmerMinHash::new(0, 3, false, 42, 0, false)];
+        let reader = FastxReader::new(&b">good\nACGT\n>bad\nACNT\n"[..]);
+
+        let err = add_records(reader, &mut sketches, false).unwrap_err();
+        assert!(err.to_string().contains("bad"));
+        assert_error_code!(err, InvalidDNA);
+    }
+
+    #[test]
+    fn add_protein_fasta() {
+        let mut sketches = vec![KmerMinHash::new(100, 9, true, 42, 0, false)];
+        let reader = FastxReader::new(&b">p1\nMRVLKF\nGG\n>p2\nMR*\n"[..]);
+        assert_eq!(
+            add_protein_records(reader, &mut sketches, false).unwrap(),
+            2
+        );
+        assert_eq!(sketches[0].size(), 7);
+
+        let reader = FastxReader::new(&b">p1\nMRV\n>bad\nMR1V\n>p3\nGGG\n"[..]);
+        assert!(add_protein_records(reader, &mut sketches.clone(), false).is_err());
+
+        let reader = FastxReader::new(&b">p1\nMRV\n>bad\nMR1V\n>p3\nGGG\n"[..]);
+        assert_eq!(add_protein_records(reader, &mut sketches, true).unwrap(), 2);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_matches_sequential() {
+        // simple LCG, so the test doesn't depend on rand
+        let mut state: u64 = 42;
+        let mut data = Vec::new();
+        for i in 0..200 {
+            data.extend_from_slice(format!(">seq{}\n", i).as_bytes());
+            for _ in 0..150 {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1);
+                data.push(b"ACGT"[(state >> 62) as usize]);
+            }
+            data.push(b'\n');
+        }
+        // repeat some records so abundances are larger than 1
+        let repeated = data[..data.len() / 4].to_vec();
+        data.extend_from_slice(&repeated);
+
+        let mut templates = vec![
+            KmerMinHash::new(50, 21, false, 42, 0, true),
+            KmerMinHash::new_scaled(10, 31, false, 42, true),
+            KmerMinHash::new(0, 21, false, 42, max_hash_for_scaled(5), false),
+        ];
+        templates[2].track_cardinality(10).unwrap();
+
+        let mut sequential = templates.clone();
+        let added = add_records(FastxReader::new(&data[..]), &mut sequential, false).unwrap();
+
+        for &chunk_size in &[1, 7, 1000] {
+            let mut parallel = templates.clone();
+            let reader = FastxReader::new(&data[..]);
+            assert_eq!(
+                add_records_parallel(reader, &mut parallel, false, chunk_size).unwrap(),
+                added
+            );
+            for (par, seq) in parallel.iter().zip(&sequential) {
+                assert_eq!(
+                    serde_json::to_string(par).unwrap(),
+                    serde_json::to_string(seq).unwrap()
+                );
+            }
+        }
+
+        let reader = FastxReader::new(&b">good\nACGTACGT\n>bad\nACNT\n>p3\nACGTT\n"[..]);
+        let mut sketches = vec![KmerMinHash::new(0, 3, false, 42, 0, false)];
+        let err = add_records_parallel(reader, &mut sketches, false, 1).unwrap_err();
+        assert!(err.to_string().contains("bad"));
+    }
+
+    #[test]
+    fn add_compressed_path() {
+        let mut path = std::env::temp_dir();
+        path.push(format!("sourmash-fastx-{}.fa.gz", std::process::id()));
+        {
+            let file = File::create(&path).unwrap();
+            let mut gz = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            gz.write_all(b">a\nACGTACGT\n>b\nGGGCCCAT\n").unwrap();
+            gz.finish().unwrap();
+        }
+
+        let mut sketches = vec![
+            KmerMinHash::new(0, 4, false, 42, 0, false),
+            KmerMinHash::new(0, 5, false, 42, 0, false),
+        ];
+        let added = add_path(path.to_str().unwrap(), &mut sketches, false).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut expected = KmerMinHash::new(0, 4, false, 42, 0, false);
+        expected.add_sequence(b"ACGTACGT", false).unwrap();
+        expected.add_sequence(b"GGGCCCAT", false).unwrap();
+
+        assert_eq!(added, 2);
+        assert_eq!(sketches[0], expected);
+        assert!(sketches[1].size() > 0);
+
+        assert!(add_path("/this/does/not/exist.fa", &mut sketches, false).is_err());
+    }
+}
@@ -1,16 +1,19 @@
+use std::convert::TryFrom;
 use std::ffi::CStr;
-use std::fs::File;
 use std::io;
 use std::mem;
 use std::os::raw::c_char;
 use std::ptr;
 use std::slice;
 
+use failure::Error;
 use serde_json;
 
 use crate::file::get_input;
+use crate::selection::Selection;
+use crate::stream::SignatureIter;
 use crate::utils::SourmashStr;
-use crate::{KmerMinHash, Signature, _hash_murmur};
+use crate::{_hash_murmur, HashFunction, KmerMinHash, Signature};
 
 #[no_mangle]
 pub extern "C" fn hash_murmur(kmer: *const c_char, seed: u64) -> u64 {
@@ -69,6 +72,23 @@ unsafe fn kmerminhash_add_sequence(ptr: *mut KmerMinHash, sequence: *const c_cha
 }
 }
 
+ffi_fn! {
+unsafe fn kmerminhash_add_protein(ptr: *mut KmerMinHash, sequence: *const c_char) ->
+    Result<()> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    let c_str = {
+        assert!(!sequence.is_null());
+
+        CStr::from_ptr(sequence)
+    };
+
+    mh.add_protein(c_str.to_bytes())
+}
+}
+
 #[no_mangle]
 pub extern "C" fn kmerminhash_add_hash(ptr: *mut KmerMinHash, h: u64) {
     let mh = unsafe {
@@ -196,6 +216,82 @@ pub extern "C" fn kmerminhash_is_protein(ptr: *mut KmerMinHash) -> bool {
     mh.is_protein
 }
 
+#[no_mangle]
+pub extern "C" fn kmerminhash_dayhoff(ptr: *mut KmerMinHash) -> bool {
+    let mh = unsafe {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    mh.dayhoff
+}
+
+ffi_fn! {
+unsafe fn kmerminhash_set_dayhoff(ptr: *mut KmerMinHash, dayhoff: bool) -> Result<()> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    mh.set_dayhoff(dayhoff)
+}
+}
+
+#[no_mangle]
+pub extern "C" fn kmerminhash_hp(ptr: *mut KmerMinHash) -> bool {
+    let mh = unsafe {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    mh.hp
+}
+
+ffi_fn! {
+unsafe fn kmerminhash_set_hp(ptr: *mut KmerMinHash, hp: bool) -> Result<()> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    mh.set_hp(hp)
+}
+}
+
+#[no_mangle]
+pub extern "C" fn kmerminhash_hash_function(ptr: *mut KmerMinHash) -> HashFunction {
+    let mh = unsafe {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    mh.hash_function
+}
+
+ffi_fn! {
+unsafe fn kmerminhash_set_hash_function(ptr: *mut KmerMinHash, hash_function: u32) -> Result<()> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    mh.set_hash_function(HashFunction::try_from(hash_function)?)
+}
+}
+
+#[no_mangle]
+pub extern "C" fn kmerminhash_translation_table(ptr: *mut KmerMinHash) -> u8 {
+    let mh = unsafe {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    mh.translation_table
+}
+
+ffi_fn! {
+unsafe fn kmerminhash_set_translation_table(ptr: *mut KmerMinHash, table: u8) -> Result<()> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    mh.set_translation_table(table)
+}
+}
+
 #[no_mangle]
 pub extern "C" fn kmerminhash_seed(ptr: *mut KmerMinHash) -> u64 {
     let mh = unsafe {
@@ -214,6 +310,25 @@ pub extern "C" fn kmerminhash_track_abundance(ptr: *mut KmerMinHash) -> bool {
     mh.abunds.is_some()
 }
 
+ffi_fn! {
+unsafe fn kmerminhash_track_cardinality(ptr: *mut KmerMinHash, p: u8) -> Result<()> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    mh.track_cardinality(p)
+}
+}
+
+#[no_mangle]
+pub extern "C" fn kmerminhash_cardinality(ptr: *mut KmerMinHash) -> u64 {
+    let mh = unsafe {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    mh.cardinality().unwrap_or(0)
+}
+
 #[no_mangle]
 pub extern "C" fn kmerminhash_num(ptr: *mut KmerMinHash) -> u32 {
     let mh = unsafe {
@@ -241,6 +356,15 @@ pub extern "C" fn kmerminhash_max_hash(ptr: *mut KmerMinHash) -> u64 {
     mh.max_hash
 }
 
+#[no_mangle]
+pub extern "C" fn kmerminhash_scaled(ptr: *mut KmerMinHash) -> u64 {
+    let mh = unsafe {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    mh.scaled()
+}
+
 ffi_fn! {
 unsafe fn kmerminhash_merge(ptr: *mut KmerMinHash, other: *const KmerMinHash) -> Result<()> {
     let mh = {
@@ -257,6 +381,105 @@ unsafe fn kmerminhash_merge(ptr: *mut KmerMinHash, other: *const KmerMinHash) ->
 }
 }
 
+ffi_fn! {
+unsafe fn kmerminhash_union(ptr: *mut KmerMinHash, other: *const KmerMinHash)
+    -> Result<*mut KmerMinHash> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    let other_mh = {
+       assert!(!other.is_null());
+       &*other
+    };
+
+    Ok(Box::into_raw(Box::new(mh.union(other_mh)?)))
+}
+}
+
+ffi_fn! {
+unsafe fn kmerminhash_difference(ptr: *mut KmerMinHash, other: *const KmerMinHash)
+    -> Result<*mut KmerMinHash> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    let other_mh = {
+       assert!(!other.is_null());
+       &*other
+    };
+
+    Ok(Box::into_raw(Box::new(mh.difference(other_mh)?)))
+}
+}
+
+ffi_fn! {
+unsafe fn kmerminhash_symmetric_difference(ptr: *mut KmerMinHash, other: *const KmerMinHash)
+    -> Result<*mut KmerMinHash> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    let other_mh = {
+       assert!(!other.is_null());
+       &*other
+    };
+
+    Ok(Box::into_raw(Box::new(mh.symmetric_difference(other_mh)?)))
+}
+}
+
+ffi_fn! {
+unsafe fn kmerminhash_filter_abundance(ptr: *mut KmerMinHash, min_abundance: u64, max_abundance: u64)
+    -> Result<*mut KmerMinHash> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+
+    // 0 means no upper limit
+    let max_abundance = if max_abundance == 0 { None } else { Some(max_abundance) };
+    Ok(Box::into_raw(Box::new(mh.filter_abundance(min_abundance, max_abundance)?)))
+}
+}
+
+ffi_fn! {
+unsafe fn kmerminhash_flatten(ptr: *mut KmerMinHash) -> Result<*mut KmerMinHash> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+
+    Ok(Box::into_raw(Box::new(mh.flatten())))
+}
+}
+
+#[no_mangle]
+pub extern "C" fn kmerminhash_remove_hash(ptr: *mut KmerMinHash, h: u64) {
+    let mh = unsafe {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+
+    mh.remove_hash(h);
+}
+
+ffi_fn! {
+unsafe fn kmerminhash_remove_many(ptr: *mut KmerMinHash, hashes_ptr: *const u64, insize: usize)
+    -> Result<()> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    let hashes = {
+        assert!(!hashes_ptr.is_null());
+        slice::from_raw_parts(hashes_ptr, insize)
+    };
+
+    mh.remove_many(hashes)
+}
+}
+
 ffi_fn! {
 unsafe fn kmerminhash_add_from(ptr: *mut KmerMinHash, other: *const KmerMinHash)
     -> Result<()> {
@@ -324,6 +547,118 @@ unsafe fn kmerminhash_compare(ptr: *mut KmerMinHash, other: *const KmerMinHash)
 }
 }
 
+ffi_fn! {
+unsafe fn kmerminhash_contained_by(ptr: *mut KmerMinHash, other: *const KmerMinHash)
+    -> Result<f64> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    let other_mh = {
+       assert!(!other.is_null());
+       &*other
+    };
+
+    mh.contained_by(other_mh)
+}
+}
+
+ffi_fn! {
+unsafe fn kmerminhash_max_containment(ptr: *mut KmerMinHash, other: *const KmerMinHash)
+    -> Result<f64> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    let other_mh = {
+       assert!(!other.is_null());
+       &*other
+    };
+
+    mh.max_containment(other_mh)
+}
+}
+
+ffi_fn! {
+unsafe fn kmerminhash_containment_ani(ptr: *mut KmerMinHash, other: *const KmerMinHash)
+    -> Result<f64> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    let other_mh = {
+       assert!(!other.is_null());
+       &*other
+    };
+
+    mh.containment_ani(other_mh)
+}
+}
+
+ffi_fn! {
+unsafe fn kmerminhash_max_containment_ani(ptr: *mut KmerMinHash, other: *const KmerMinHash)
+    -> Result<f64> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    let other_mh = {
+       assert!(!other.is_null());
+       &*other
+    };
+
+    mh.max_containment_ani(other_mh)
+}
+}
+
+ffi_fn! {
+unsafe fn kmerminhash_jaccard_ani(ptr: *mut KmerMinHash, other: *const KmerMinHash)
+    -> Result<f64> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    let other_mh = {
+       assert!(!other.is_null());
+       &*other
+    };
+
+    mh.jaccard_ani(other_mh)
+}
+}
+
+ffi_fn! {
+unsafe fn kmerminhash_similarity(ptr: *mut KmerMinHash, other: *const KmerMinHash, ignore_abundance: bool)
+    -> Result<f64> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    let other_mh = {
+       assert!(!other.is_null());
+       &*other
+    };
+
+    mh.similarity(other_mh, ignore_abundance)
+}
+}
+
+ffi_fn! {
+unsafe fn kmerminhash_angular_similarity(ptr: *mut KmerMinHash, other: *const KmerMinHash)
+    -> Result<f64> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+    let other_mh = {
+       assert!(!other.is_null());
+       &*other
+    };
+
+    mh.angular_similarity(other_mh)
+}
+}
+
 // Signature methods
 
 #[no_mangle]
@@ -415,6 +750,17 @@ unsafe fn signature_set_mh(ptr: *mut Signature, other: *const KmerMinHash) ->
 }
 }
 
+ffi_fn! {
+unsafe fn kmerminhash_md5sum(ptr: *mut KmerMinHash) -> Result<SourmashStr> {
+    let mh = {
+        assert!(!ptr.is_null());
+        &mut *ptr
+    };
+
+    Ok(SourmashStr::from_string(mh.md5sum()))
+}
+}
+
 ffi_fn! {
 unsafe fn signature_get_name(ptr: *mut Signature) -> Result<SourmashStr> {
     let sig = {
@@ -533,6 +879,25 @@ unsafe fn signatures_save_buffer(ptr: *mut *mut Signature, size: usize) -> Resul
 }
 }
 
+fn load_selected<R: io::Read>(
+    rdr: &mut R,
+    ignore_md5sum: bool,
+    ksize: usize,
+    moltype: Option<&str>,
+) -> Result<Vec<Signature>, Error> {
+    let selection = Selection {
+        ksize: if ksize == 0 { None } else { Some(ksize as u32) },
+        moltype: moltype.map(String::from),
+        ..Default::default()
+    };
+
+    let mut iter = SignatureIter::new(rdr)?;
+    if !ignore_md5sum {
+        iter = iter.check_md5sum();
+    }
+    iter.with_selection(selection).collect()
+}
+
 ffi_fn! {
 unsafe fn signatures_load_path(ptr: *const c_char,
                                ignore_md5sum: bool,
@@ -552,10 +917,8 @@ unsafe fn signatures_load_path(ptr: *const c_char,
         }
     };
 
-    // TODO: implement ignore_md5sum
-
-    let (mut input, _) = get_input(buf.to_str()?);
-    let filtered_sigs = Signature::load_signatures(&mut input, ksize, moltype, None)?;
+    let (mut input, _) = get_input(buf.to_str()?)?;
+    let filtered_sigs = load_selected(&mut input, ignore_md5sum, ksize, moltype)?;
 
     let ptr_sigs: Vec<*mut Signature> = filtered_sigs.into_iter().map(|x| {
       Box::into_raw(Box::new(x)) as *mut Signature
@@ -587,10 +950,8 @@ unsafe fn signatures_load_buffer(ptr: *const c_char,
         }
     };
 
-    // TODO: implement ignore_md5sum
-
     let mut reader = io::BufReader::new(buf);
-    let filtered_sigs = Signature::load_signatures(&mut reader, ksize, moltype, None)?;
+    let filtered_sigs = load_selected(&mut reader, ignore_md5sum, ksize, moltype)?;
 
     let ptr_sigs: Vec<*mut Signature> = filtered_sigs.into_iter().map(|x| {
       Box::into_raw(Box::new(x)) as *mut Signature
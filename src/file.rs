@@ -31,11 +31,12 @@ use xz2;
 /* standard use */
 use std::fs::File;
 use std::io;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
 
 enum_from_primitive! {
     #[repr(u64)]
-    #[derive(Debug, PartialEq)]
+    #[derive(Debug, Clone, Copy, PartialEq)]
     pub enum CompressionFormat {
         Gzip = 0x1F8B,
         Bzip = 0x425A,
@@ -44,54 +45,62 @@ enum_from_primitive! {
     }
 }
 
-pub fn get_input(input_name: &str) -> (Box<dyn io::Read>, CompressionFormat) {
+pub fn get_input(input_name: &str) -> io::Result<(Box<dyn io::Read>, CompressionFormat)> {
     // choose std::io::stdin or open file
-    if input_name == "-" {
-        return (Box::new(get_readable(input_name)), CompressionFormat::No);
-    }
-
-    return get_readable_file(input_name);
+    get_decoder(get_readable(input_name)?)
 }
 
-pub fn get_readable_file(input_name: &str) -> (Box<dyn io::Read>, CompressionFormat) {
-    let raw_input = get_readable(input_name);
+pub fn get_readable_file(input_name: &str) -> io::Result<(Box<dyn io::Read>, CompressionFormat)> {
+    get_decoder(BufReader::new(File::open(input_name)?))
+}
 
-    // check compression
-    let compression = get_compression(raw_input);
+/// Wrap `in_stream` with a decoder for its compression format, detected from
+/// the first bytes.
+pub fn get_decoder<'a, R: io::Read + 'a>(
+    mut in_stream: R,
+) -> io::Result<(Box<dyn io::Read + 'a>, CompressionFormat)> {
+    // read the magic numbers and put them back in front of the stream
+    let mut magic = Vec::with_capacity(5);
+    (&mut in_stream).take(5).read_to_end(&mut magic)?;
+    let compression = get_compression(&magic[..]);
+    let raw_input = io::Cursor::new(magic).chain(in_stream);
 
     // return readable and compression status
-    match compression {
+    Ok(match compression {
         CompressionFormat::Gzip => (
-            Box::new(flate2::read::GzDecoder::new(get_readable(input_name))),
+            Box::new(flate2::read::GzDecoder::new(raw_input)),
             CompressionFormat::Gzip,
         ),
         CompressionFormat::Bzip => (
-            Box::new(bzip2::read::BzDecoder::new(get_readable(input_name))),
+            Box::new(bzip2::read::BzDecoder::new(raw_input)),
             CompressionFormat::Bzip,
         ),
         CompressionFormat::Lzma => (
-            Box::new(xz2::read::XzDecoder::new(get_readable(input_name))),
+            Box::new(xz2::read::XzDecoder::new(raw_input)),
             CompressionFormat::Lzma,
         ),
-        CompressionFormat::No => (Box::new(get_readable(input_name)), CompressionFormat::No),
-    }
+        CompressionFormat::No => (Box::new(raw_input), CompressionFormat::No),
+    })
 }
 
-pub fn get_readable(input_name: &str) -> Box<dyn io::Read> {
-    match input_name {
+pub fn get_readable(input_name: &str) -> io::Result<Box<dyn io::Read>> {
+    Ok(match input_name {
         "-" => Box::new(BufReader::new(io::stdin())),
-        _ => Box::new(BufReader::new(
-            File::open(input_name).expect(&format!("Can't open input file {}", input_name)),
-        )),
-    }
+        _ => Box::new(BufReader::new(File::open(input_name)?)),
+    })
 }
 
-fn get_compression(mut in_stream: Box<dyn io::Read>) -> CompressionFormat {
-    let mut buf = vec![0u8; 5];
+fn get_compression<R: io::Read>(mut in_stream: R) -> CompressionFormat {
+    let mut buf = [0u8; 5];
 
-    in_stream
-        .read_exact(&mut buf)
-        .expect("Error durring reading first bit of file");
+    // files shorter than the magic numbers can't be compressed
+    let mut filled = 0;
+    while filled < buf.len() {
+        match in_stream.read(&mut buf[filled..]) {
+            Ok(0) | Err(_) => return CompressionFormat::No,
+            Ok(n) => filled += n,
+        }
+    }
 
     let mut five_bit_val: u64 = 0;
     for i in 0..5 {
@@ -113,20 +122,76 @@ fn get_compression(mut in_stream: Box<dyn io::Read>) -> CompressionFormat {
     }
 }
 
-pub fn get_output(output_name: &str, format: CompressionFormat) -> Box<dyn io::Write> {
-    match format {
-        CompressionFormat::Gzip => Box::new(flate2::write::GzEncoder::new(
-            get_writable(output_name),
-            flate2::Compression::best(),
-        )),
-        CompressionFormat::Bzip => Box::new(bzip2::write::BzEncoder::new(
-            get_writable(output_name),
-            bzip2::Compression::Best,
-        )),
-        CompressionFormat::Lzma => {
-            Box::new(xz2::write::XzEncoder::new(get_writable(output_name), 9))
+pub fn get_output<P: AsRef<Path>>(output_name: P, format: CompressionFormat) -> io::Result<Output> {
+    Ok(Output::new(get_writable(output_name)?, format))
+}
+
+/// Writer returned by `get_output`. The end of a compressed stream is only
+/// written by `finish`, so it must be called once everything is written:
+/// dropping an `Output` ignores any error.
+#[must_use = "outputs must be closed with `finish`"]
+pub enum Output {
+    Gzip(flate2::write::GzEncoder<Box<dyn io::Write>>),
+    Bzip(bzip2::write::BzEncoder<Box<dyn io::Write>>),
+    Lzma(xz2::write::XzEncoder<Box<dyn io::Write>>),
+    No(Box<dyn io::Write>),
+}
+
+impl Output {
+    /// Compress everything written into `wtr` with `format`.
+    pub fn new(wtr: Box<dyn io::Write>, format: CompressionFormat) -> Output {
+        match format {
+            CompressionFormat::Gzip => Output::Gzip(flate2::write::GzEncoder::new(
+                wtr,
+                flate2::Compression::best(),
+            )),
+            CompressionFormat::Bzip => {
+                Output::Bzip(bzip2::write::BzEncoder::new(wtr, bzip2::Compression::Best))
+            }
+            CompressionFormat::Lzma => Output::Lzma(xz2::write::XzEncoder::new(wtr, 9)),
+            CompressionFormat::No => Output::No(wtr),
+        }
+    }
+
+    /// Finish the compressed stream and flush the underlying writer.
+    pub fn finish(self) -> io::Result<()> {
+        let mut wtr = match self {
+            Output::Gzip(encoder) => encoder.finish()?,
+            Output::Bzip(encoder) => encoder.finish()?,
+            Output::Lzma(encoder) => encoder.finish()?,
+            Output::No(wtr) => wtr,
+        };
+        wtr.flush()
+    }
+
+    fn inner(&mut self) -> &mut dyn io::Write {
+        match self {
+            Output::Gzip(encoder) => encoder,
+            Output::Bzip(encoder) => encoder,
+            Output::Lzma(encoder) => encoder,
+            Output::No(wtr) => wtr,
         }
-        CompressionFormat::No => Box::new(get_writable(output_name)),
+    }
+}
+
+impl io::Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner().flush()
+    }
+}
+
+/// Compression format matching the extension of `output_name` (`.gz`, `.bz2`,
+/// `.xz` or `.lzma`).
+pub fn compression_from_extension<P: AsRef<Path>>(output_name: P) -> CompressionFormat {
+    match output_name.as_ref().extension().and_then(|e| e.to_str()) {
+        Some("gz") => CompressionFormat::Gzip,
+        Some("bz2") => CompressionFormat::Bzip,
+        Some("xz") | Some("lzma") => CompressionFormat::Lzma,
+        _ => CompressionFormat::No,
     }
 }
 
@@ -147,13 +212,13 @@ pub fn choose_compression(
     }
 }
 
-fn get_writable(output_name: &str) -> Box<dyn io::Write> {
-    match output_name {
-        "-" => Box::new(BufWriter::new(io::stdout())),
-        _ => Box::new(BufWriter::new(
-            File::create(output_name).expect(&format!("Can't open output file {}", output_name)),
-        )),
-    }
+fn get_writable<P: AsRef<Path>>(output_name: P) -> io::Result<Box<dyn io::Write>> {
+    let output_name = output_name.as_ref();
+    Ok(if output_name == Path::new("-") {
+        Box::new(BufWriter::new(io::stdout()))
+    } else {
+        Box::new(BufWriter::new(File::create(output_name)?))
+    })
 }
 
 #[cfg(test)]
@@ -179,6 +244,7 @@ mod test {
             get_compression(Box::new(LZMA_FILE)),
             CompressionFormat::Lzma
         );
+        assert_eq!(get_compression(Box::new(&b">"[..])), CompressionFormat::No);
     }
 
     #[test]
@@ -208,4 +274,80 @@ mod test {
             CompressionFormat::Lzma
         );
     }
+
+    #[test]
+    fn compression_from_output_name() {
+        assert_eq!(
+            compression_from_extension("out.sig.gz"),
+            CompressionFormat::Gzip
+        );
+        assert_eq!(
+            compression_from_extension("out.sig.bz2"),
+            CompressionFormat::Bzip
+        );
+        assert_eq!(
+            compression_from_extension("out.xz"),
+            CompressionFormat::Lzma
+        );
+        assert_eq!(compression_from_extension("out.sig"), CompressionFormat::No);
+        assert_eq!(compression_from_extension("-"), CompressionFormat::No);
+    }
+
+    #[test]
+    fn decode_any_reader() {
+        use std::io::Write;
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(b"[{}]").unwrap();
+        let compressed = gz.finish().unwrap();
+
+        for (input, format) in vec![
+            (&compressed[..], CompressionFormat::Gzip),
+            (&b"[{}]"[..], CompressionFormat::No),
+            (&b"[]"[..], CompressionFormat::No),
+        ] {
+            let (mut rdr, detected) = get_decoder(input).unwrap();
+            let mut content = String::new();
+            rdr.read_to_string(&mut content).unwrap();
+            assert_eq!(detected, format);
+            assert!(content.starts_with('['));
+        }
+    }
+
+    /// Accepts `limit` bytes, then fails.
+    struct Limited {
+        limit: usize,
+    }
+
+    impl Write for Limited {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if buf.len() > self.limit {
+                return Err(io::Error::new(io::ErrorKind::Other, "no space left"));
+            }
+            self.limit -= buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn finish_reports_errors() {
+        for format in &[
+            CompressionFormat::Gzip,
+            CompressionFormat::Bzip,
+            CompressionFormat::Lzma,
+        ] {
+            // the compressed data only gets written by finish
+            let mut out = Output::new(Box::new(Limited { limit: 10 }), *format);
+            out.write_all(b"[]").unwrap();
+            assert!(out.finish().is_err(), "{:?}", format);
+
+            let mut out = Output::new(Box::new(Limited { limit: 1000 }), *format);
+            out.write_all(b"[]").unwrap();
+            out.finish().unwrap();
+        }
+    }
 }
@@ -0,0 +1,181 @@
+//! HyperLogLog cardinality estimation.
+//!
+//! Sketches only keep a fraction of the hashes they see, so the number of
+//! distinct k-mers in the input is estimated separately. The hashes added to
+//! a `KmerMinHash` are already uniformly distributed, so they are used
+//! directly: the first `p` bits select a register, and each register keeps
+//! the largest position of the first set bit in the remaining bits.
+
+use failure::Error;
+use serde::de::{Deserialize, Deserializer, Error as DeError};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::errors::SourmashError;
+
+pub const MIN_PRECISION: u8 = 4;
+pub const MAX_PRECISION: u8 = 18;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HyperLogLog {
+    /// Number of bits used to select a register (there are `2^p` registers).
+    p: u8,
+    registers: Vec<u8>,
+}
+
+impl<'de> Deserialize<'de> for HyperLogLog {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct TempHLL {
+            p: u8,
+            registers: Vec<u8>,
+        }
+
+        let tmp = TempHLL::deserialize(deserializer)?;
+        let mut hll = HyperLogLog::new(tmp.p).map_err(D::Error::custom)?;
+        if tmp.registers.len() != hll.registers.len() {
+            return Err(D::Error::custom(format!(
+                "expected {} HyperLogLog registers, found {}",
+                hll.registers.len(),
+                tmp.registers.len()
+            )));
+        }
+        hll.registers = tmp.registers;
+        Ok(hll)
+    }
+}
+
+impl HyperLogLog {
+    /// New estimator with `2^p` registers. The standard error is about
+    /// `1.04 / sqrt(2^p)`, so `p = 12` (4 KB) gives estimates within ~1.6%.
+    pub fn new(p: u8) -> Result<HyperLogLog, Error> {
+        if !(MIN_PRECISION..=MAX_PRECISION).contains(&p) {
+            return Err(SourmashError::InvalidHllPrecision { p }.into());
+        }
+        Ok(HyperLogLog {
+            p,
+            registers: vec![0; 1 << p],
+        })
+    }
+
+    pub fn precision(&self) -> u8 {
+        self.p
+    }
+
+    pub(crate) fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    pub(crate) fn registers_mut(&mut self) -> &mut [u8] {
+        &mut self.registers
+    }
+
+    pub fn add_hash(&mut self, hash: u64) {
+        let index = (hash >> (64 - self.p)) as usize;
+        let rest = hash << self.p;
+        let rank = u32::min(rest.leading_zeros(), 64 - u32::from(self.p)) + 1;
+
+        let register = &mut self.registers[index];
+        if rank as u8 > *register {
+            *register = rank as u8;
+        }
+    }
+
+    /// Estimated number of distinct hashes added.
+    pub fn cardinality(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&r| 2f64.powi(-i32::from(r)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+
+        // linear counting is more accurate for small cardinalities. With
+        // 64-bit hashes there is no need for a large range correction.
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if estimate <= 2.5 * m && zeros != 0 {
+            return (m * (m / zeros as f64).ln()).round() as u64;
+        }
+        estimate.round() as u64
+    }
+
+    /// Combine with another estimator, as if all its hashes were added here.
+    pub fn merge(&mut self, other: &HyperLogLog) -> Result<(), Error> {
+        if self.p != other.p {
+            return Err(SourmashError::MismatchHllPrecision.into());
+        }
+        for (r, o) in self.registers.iter_mut().zip(&other.registers) {
+            *r = u8::max(*r, *o);
+        }
+        Ok(())
+    }
+
+    /// An empty estimator with the same precision.
+    pub fn copy_empty(&self) -> HyperLogLog {
+        HyperLogLog {
+            p: self.p,
+            registers: vec![0; self.registers.len()],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::_hash_murmur;
+
+    fn relative_error(estimate: u64, actual: u64) -> f64 {
+        (estimate as f64 - actual as f64).abs() / actual as f64
+    }
+
+    #[test]
+    fn estimates() {
+        let mut hll = HyperLogLog::new(12).unwrap();
+        assert_eq!(hll.cardinality(), 0);
+
+        for i in 0..100u64 {
+            // duplicates don't change the estimate
+            hll.add_hash(_hash_murmur(&i.to_le_bytes(), 42));
+            hll.add_hash(_hash_murmur(&i.to_le_bytes(), 42));
+        }
+        assert!(relative_error(hll.cardinality(), 100) < 0.05);
+
+        for i in 100..200_000u64 {
+            hll.add_hash(_hash_murmur(&i.to_le_bytes(), 42));
+        }
+        assert!(relative_error(hll.cardinality(), 200_000) < 0.05);
+    }
+
+    #[test]
+    fn merge() {
+        let mut a = HyperLogLog::new(10).unwrap();
+        let mut b = a.copy_empty();
+        let mut all = a.copy_empty();
+        for i in 0..5000u64 {
+            let hash = _hash_murmur(&i.to_le_bytes(), 42);
+            if i % 3 == 0 {
+                a.add_hash(hash);
+            } else {
+                b.add_hash(hash);
+            }
+            all.add_hash(hash);
+        }
+
+        a.merge(&b).unwrap();
+        assert_eq!(a, all);
+
+        assert!(a.merge(&HyperLogLog::new(11).unwrap()).is_err());
+        assert!(HyperLogLog::new(3).is_err());
+        assert!(HyperLogLog::new(19).is_err());
+    }
+}
@@ -43,6 +43,18 @@ pub trait Index {
 pub trait Comparable<O> {
     fn similarity(&self, other: &O) -> f64;
     fn containment(&self, other: &O) -> f64;
+
+    /// Similarity without abundance weighting. Defaults to `similarity` for
+    /// anything that doesn't track abundances.
+    fn jaccard(&self, other: &O) -> f64 {
+        self.similarity(other)
+    }
+
+    /// Containment of the smaller of the two in the other. Defaults to
+    /// `containment`.
+    fn max_containment(&self, other: &O) -> f64 {
+        self.containment(other)
+    }
 }
 
 impl<'a, N, L> Comparable<L> for &'a N
@@ -56,6 +68,14 @@ where
     fn containment(&self, other: &L) -> f64 {
         (*self).containment(&other)
     }
+
+    fn jaccard(&self, other: &L) -> f64 {
+        (*self).jaccard(&other)
+    }
+
+    fn max_containment(&self, other: &L) -> f64 {
+        (*self).max_containment(&other)
+    }
 }
 
 #[derive(Deserialize)]
@@ -143,15 +163,43 @@ impl Comparable<Leaf<Signature>> for Leaf<Signature> {
         }
     }
 
+    fn jaccard(&self, other: &Leaf<Signature>) -> f64 {
+        if let Some(storage) = &self.storage {
+            let ng: &Signature = self.data(&**storage).unwrap();
+            let ong: &Signature = other.data(&**storage).unwrap();
+
+            // TODO: select the right signatures...
+            ng.signatures[0].jaccard(&ong.signatures[0]).unwrap()
+        } else {
+            // TODO: in this case storage is not set up,
+            // so we should throw an error?
+            0.0
+        }
+    }
+
     fn containment(&self, other: &Leaf<Signature>) -> f64 {
         if let Some(storage) = &self.storage {
             let ng: &Signature = self.data(&**storage).unwrap();
             let ong: &Signature = other.data(&**storage).unwrap();
 
             // TODO: select the right signatures...
-            let common = ng.signatures[0].count_common(&ong.signatures[0]).unwrap();
-            let size = ng.signatures[0].mins.len();
-            common as f64 / size as f64
+            ng.signatures[0].contained_by(&ong.signatures[0]).unwrap()
+        } else {
+            // TODO: in this case storage is not set up,
+            // so we should throw an error?
+            0.0
+        }
+    }
+
+    fn max_containment(&self, other: &Leaf<Signature>) -> f64 {
+        if let Some(storage) = &self.storage {
+            let ng: &Signature = self.data(&**storage).unwrap();
+            let ong: &Signature = other.data(&**storage).unwrap();
+
+            // TODO: select the right signatures...
+            ng.signatures[0]
+                .max_containment(&ong.signatures[0])
+                .unwrap()
         } else {
             // TODO: in this case storage is not set up,
             // so we should throw an error?
@@ -14,7 +14,7 @@ use serde_derive::Deserialize;
 use crate::index::nodegraph::Nodegraph;
 use crate::index::storage::{FSStorage, ReadData, Storage, StorageInfo};
 use crate::index::{Comparable, Index, Leaf, LeafInfo};
-use crate::Signature;
+use crate::{KmerMinHash, Signature};
 
 pub type MHBT = SBT<Node<Nodegraph>, Leaf<Signature>>;
 
@@ -232,6 +232,30 @@ impl Comparable<Node<Nodegraph>> for Node<Nodegraph> {
 
 impl Comparable<Leaf<Signature>> for Node<Nodegraph> {
     fn similarity(&self, other: &Leaf<Signature>) -> f64 {
+        if let Some(storage) = &self.storage {
+            let ng: &Nodegraph = self.data(&**storage).unwrap();
+            let oth: &Signature = other.data(&**storage).unwrap();
+
+            // TODO: select the right signatures...
+            let sig = &oth.signatures[0];
+            if sig.size() == 0 {
+                return 0.0;
+            }
+
+            let jaccard = self.jaccard(other);
+            match &sig.abunds {
+                // leaves tracking abundances are compared with the angular
+                // similarity, which the Jaccard bound doesn't cover
+                Some(abunds) => f64::max(jaccard, angular_bound(ng, sig, abunds)),
+                None => jaccard,
+            }
+        } else {
+            // TODO: throw error, storage not initialized
+            0.0
+        }
+    }
+
+    fn jaccard(&self, other: &Leaf<Signature>) -> f64 {
         if let Some(storage) = &self.storage {
             let ng: &Nodegraph = self.data(&**storage).unwrap();
             let oth: &Signature = other.data(&**storage).unwrap();
@@ -274,6 +298,29 @@ impl Comparable<Leaf<Signature>> for Node<Nodegraph> {
             0.0
         }
     }
+
+    fn max_containment(&self, other: &Leaf<Signature>) -> f64 {
+        if let Some(storage) = &self.storage {
+            let ng: &Nodegraph = self.data(&**storage).unwrap();
+            let oth: &Signature = other.data(&**storage).unwrap();
+
+            // TODO: select the right signatures...
+            let sig = &oth.signatures[0];
+            if sig.size() == 0 {
+                return 0.0;
+            }
+
+            let matches: usize = sig.mins.iter().map(|h| ng.get(*h)).sum();
+
+            // No leaf below is smaller than min_n_below, so this is an upper
+            // bound for the max containment of any of them.
+            let min_n_below = self.metadata["min_n_below"] as usize;
+            matches as f64 / usize::max(1, usize::min(sig.size(), min_n_below)) as f64
+        } else {
+            // TODO: throw error, storage not initialized
+            0.0
+        }
+    }
 }
 
 impl<S: Storage + ?Sized> ReadData<Nodegraph, S> for Node<Nodegraph> {
@@ -517,11 +564,44 @@ impl BinaryTree {
     }
 }
 
+/// Upper bound for the angular similarity between `sig` and any leaf below
+/// `ng`. Only the hashes of `sig` found in `ng` can be shared, so by
+/// Cauchy-Schwarz the cosine is at most the norm of their abundances over the
+/// norm of all abundances in `sig`.
+fn angular_bound(ng: &Nodegraph, sig: &KmerMinHash, abunds: &[u64]) -> f64 {
+    // num sketches are only compared up to the largest hash of the smaller
+    // sketch, which isn't known here
+    if sig.num != 0 {
+        let found = sig.mins.iter().any(|h| ng.get(*h) == 1);
+        return if found { 1.0 } else { 0.0 };
+    }
+
+    let (mut found, mut total) = (0., 0.);
+    for (hash, &abund) in sig.mins.iter().zip(abunds) {
+        let sq = (abund as f64) * (abund as f64);
+        total += sq;
+        if ng.get(*hash) == 1 {
+            found += sq;
+        }
+    }
+    if total == 0. {
+        return 0.0;
+    }
+
+    let cos = f64::min((found / total).sqrt(), 1.);
+    1. - 2. * cos.acos() / std::f64::consts::PI
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::index::linear::{LinearIndex, LinearIndexBuilder};
-    use crate::index::search::{search_minhashes, search_minhashes_containment};
+    use crate::index::search::{
+        search_minhashes, search_minhashes_ani, search_minhashes_containment,
+        search_minhashes_ignore_abundance, search_minhashes_max_containment,
+    };
+    use crate::index::LeafBuilder;
+    use crate::max_hash_for_scaled;
 
     #[test]
     fn load_sbt() {
@@ -587,6 +667,25 @@ mod test {
         assert_eq!(results.len(), 4);
         println!("results: {:?}", results);
         println!("leaf: {:?}", leaf);
+
+        // internal nodes must not prune leaves that match
+        for threshold in &[0.1, 0.5, 0.9] {
+            let results = sbt
+                .find(search_minhashes_max_containment, &leaf, *threshold)
+                .unwrap();
+            let linear_results = linear
+                .find(search_minhashes_max_containment, &leaf, *threshold)
+                .unwrap();
+            assert_eq!(results.len(), linear_results.len());
+            assert!(!results.is_empty());
+        }
+
+        let mh = &leaf.data(&*sbt.storage).unwrap().signatures[0];
+        let results = linear.find(search_minhashes_ani(mh), &leaf, 0.99).unwrap();
+        let max_containment = linear
+            .find(search_minhashes_max_containment, &leaf, 0.99f64.powi(31))
+            .unwrap();
+        assert_eq!(results.len(), max_containment.len());
     }
 
     #[test]
@@ -599,4 +698,103 @@ mod test {
         let new_sbt: MHBT = scaffold(sbt.leaves());
         assert_eq!(new_sbt.leaves().len(), 7);
     }
+
+    type SearchFn = fn(&dyn Comparable<Leaf<Signature>>, &Leaf<Signature>, f64) -> bool;
+
+    #[test]
+    fn search_abundances() {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("sourmash-sbt-abund-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let sketch = |abunds: &[(u64, u64)]| {
+            let mut mh = KmerMinHash::new(0, 21, false, 42, max_hash_for_scaled(1), true);
+            mh.add_many_with_abund(abunds).unwrap();
+            let mut sig = Signature::default();
+            sig.signatures = vec![mh];
+            sig
+        };
+
+        // the query is dominated by hash 1
+        let mut query: Vec<_> = (2..=20).map(|h| (h, 1)).collect();
+        query.push((1, 100));
+        // shares only hash 1: low Jaccard, high angular similarity
+        let mut heavy: Vec<_> = (100..=140).map(|h| (h, 1)).collect();
+        heavy.push((1, 100));
+        // shares everything but hash 1: the other way around
+        let light: Vec<_> = (2..=20).map(|h| (h, 1)).collect();
+
+        Signature::save_path(&[sketch(&query)], dir.join("query")).unwrap();
+
+        // the root covers both leaves, and each of its children one of them
+        let save_node = |name: &str, leaves: &[&[(u64, u64)]]| {
+            let mut ng = Nodegraph::new(&[1021], 21);
+            for abunds in leaves {
+                abunds.iter().for_each(|(h, _)| {
+                    ng.count(*h);
+                });
+            }
+            ng.save_to_writer(&mut File::create(dir.join(name)).unwrap())
+                .unwrap();
+        };
+        save_node("internal.0", &[&heavy, &light]);
+        save_node("internal.1", &[&heavy]);
+        save_node("internal.2", &[&light]);
+        Signature::save_path(&[sketch(&heavy)], dir.join("heavy")).unwrap();
+        Signature::save_path(&[sketch(&light)], dir.join("light")).unwrap();
+
+        let node = |pos: u64, min_n_below: usize| {
+            format!(
+                r#""{0}": {{"filename": "internal.{0}", "name": "internal.{0}",
+                          "metadata": {{"min_n_below": {1}}}}}"#,
+                pos, min_n_below
+            )
+        };
+        let sbt_json = format!(
+            r#"{{"d": 2, "version": 5,
+                "storage": {{"backend": "FSStorage", "args": {{"path": "."}}}},
+                "factory": {{"class": "GraphFactory", "args": [1, 1021, 1]}},
+                "nodes": {{{}, {}, {}}},
+                "leaves": {{"3": {{"filename": "heavy", "name": "heavy", "metadata": ""}},
+                           "5": {{"filename": "light", "name": "light", "metadata": ""}}}}}}"#,
+            node(0, light.len()),
+            node(1, heavy.len()),
+            node(2, light.len())
+        );
+        let sbt = MHBT::from_reader(&mut sbt_json.as_bytes(), &dir).unwrap();
+
+        let query: Leaf<Signature> = LeafBuilder::default()
+            .filename("query".into())
+            .name("query".into())
+            .metadata("".into())
+            .storage(Some(Rc::clone(&sbt.storage)))
+            .data(Rc::new(Lazy::new()))
+            .build()
+            .unwrap();
+        let mut linear = LinearIndexBuilder::default()
+            .storage(Rc::clone(&sbt.storage))
+            .build()
+            .unwrap();
+        for l in sbt.leaves.values() {
+            linear.insert(l);
+        }
+
+        let names = |results: Vec<&Leaf<Signature>>| {
+            let mut names: Vec<_> = results.iter().map(|l| l.name.clone()).collect();
+            names.sort();
+            names
+        };
+
+        let search_fns: Vec<(SearchFn, &str)> = vec![
+            (search_minhashes, "heavy"),
+            (search_minhashes_ignore_abundance, "light"),
+        ];
+        for (search_fn, expected) in search_fns {
+            let results = names(sbt.find(search_fn, &query, 0.5).unwrap());
+            assert_eq!(results, names(linear.find(search_fn, &query, 0.5).unwrap()));
+            assert_eq!(results, vec![expected.to_string()]);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
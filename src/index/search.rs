@@ -1,9 +1,69 @@
 use crate::index::Comparable;
+use crate::KmerMinHash;
 
 pub fn search_minhashes<L>(node: &dyn Comparable<L>, query: &L, threshold: f64) -> bool {
     node.similarity(query) > threshold
 }
 
+pub fn search_minhashes_ignore_abundance<L>(
+    node: &dyn Comparable<L>,
+    query: &L,
+    threshold: f64,
+) -> bool {
+    node.jaccard(query) > threshold
+}
+
 pub fn search_minhashes_containment<L>(node: &dyn Comparable<L>, query: &L, threshold: f64) -> bool {
     node.containment(query) > threshold
 }
+
+pub fn search_minhashes_max_containment<L>(
+    node: &dyn Comparable<L>,
+    query: &L,
+    threshold: f64,
+) -> bool {
+    node.max_containment(query) > threshold
+}
+
+/// Search function for an ANI `threshold` (estimated from max containment)
+/// against the `query` sketch. Since ANI is `containment^(1/k)`, with `k`
+/// the k-mer length of the sketch (see `KmerMinHash::kmer_length`), this is
+/// the same as searching for a max containment above `threshold^k`.
+pub fn search_minhashes_ani<L>(
+    query: &KmerMinHash,
+) -> impl Fn(&dyn Comparable<L>, &L, f64) -> bool {
+    let kmer_length = query.kmer_length() as i32;
+    move |node, query, threshold| node.max_containment(query) > threshold.powi(kmer_length)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct MaxContainment(f64);
+
+    impl Comparable<()> for MaxContainment {
+        fn similarity(&self, _other: &()) -> f64 {
+            0.0
+        }
+
+        fn containment(&self, _other: &()) -> f64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn ani_uses_kmer_length() {
+        let dna = KmerMinHash::new(100, 30, false, 42, 0, false);
+        let protein = KmerMinHash::new(100, 30, true, 42, 0, false);
+
+        // 0.9^30 is about 0.04, but protein k-mers are 10 residues long and
+        // 0.9^10 is about 0.35
+        let node = MaxContainment(0.3);
+        assert!(search_minhashes_ani(&dna)(&node, &(), 0.9));
+        assert!(!search_minhashes_ani(&protein)(&node, &(), 0.9));
+
+        let node = MaxContainment(0.4);
+        assert!(search_minhashes_ani(&protein)(&node, &(), 0.9));
+    }
+}
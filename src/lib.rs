@@ -1,3 +1,4 @@
+#[macro_use]
 pub mod errors;
 
 #[macro_use]
@@ -7,42 +8,172 @@ pub mod ffi;
 
 pub mod index;
 
+pub mod fastx;
+
+pub mod stats;
+
+pub mod hyperloglog;
+
+pub mod tracking;
+
+pub mod selection;
+
+pub mod compute;
+
+pub mod binary;
+
+pub mod stream;
+
 #[cfg(feature = "from-finch")]
 pub mod from;
 
-mod file;
+pub mod file;
+
+#[cfg(test)]
+mod test_utils;
 
-use serde::de::{Deserialize, Deserializer};
+use serde::de::{Deserialize, Deserializer, Error as DeError};
 use serde::ser::{Serialize, SerializeStruct, Serializer};
-use serde_derive::{Deserialize, Serialize};
+use serde_derive::Deserialize;
 
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::HashSet;
+use std::convert::TryFrom;
 use std::fs::File;
+use std::hash::Hasher;
 use std::io;
 use std::iter::{Iterator, Peekable};
 use std::path::Path;
 use std::str;
+use std::str::FromStr;
 
 use failure::Error;
-use lazy_static::lazy_static;
 use murmurhash3::murmurhash3_x64_128;
+use twox_hash::XxHash64;
 
 use crate::errors::SourmashError;
+use crate::file::{compression_from_extension, get_decoder, get_output, CompressionFormat};
+use crate::hyperloglog::HyperLogLog;
+use crate::selection::Selection;
+use crate::stats::{proportion_estimate, Estimate};
+use crate::stream::SignatureIter;
 
 pub fn _hash_murmur(kmer: &[u8], seed: u64) -> u64 {
     murmurhash3_x64_128(kmer, seed).0
 }
 
+/// Hash functions used to turn k-mers into the hashes stored in a sketch.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashFunction {
+    Murmur64 = 1,
+    XxHash64 = 2,
+}
+
+impl HashFunction {
+    pub fn hash(self, kmer: &[u8], seed: u64) -> u64 {
+        match self {
+            HashFunction::Murmur64 => _hash_murmur(kmer, seed),
+            HashFunction::XxHash64 => {
+                let mut hasher = XxHash64::with_seed(seed);
+                hasher.write(kmer);
+                hasher.finish()
+            }
+        }
+    }
+
+    /// Name used in the `hash_function` field of signature files.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HashFunction::Murmur64 => "0.murmur64",
+            HashFunction::XxHash64 => "0.xxhash64",
+        }
+    }
+}
+
+impl TryFrom<u32> for HashFunction {
+    type Error = Error;
+
+    fn try_from(value: u32) -> Result<HashFunction, Error> {
+        match value {
+            1 => Ok(HashFunction::Murmur64),
+            2 => Ok(HashFunction::XxHash64),
+            _ => Err(SourmashError::InvalidHashFunction {
+                function: value.to_string(),
+            }
+            .into()),
+        }
+    }
+}
+
+impl FromStr for HashFunction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<HashFunction, Error> {
+        match s {
+            "0.murmur64" => Ok(HashFunction::Murmur64),
+            "0.xxhash64" => Ok(HashFunction::XxHash64),
+            _ => Err(SourmashError::InvalidHashFunction {
+                function: s.to_string(),
+            }
+            .into()),
+        }
+    }
+}
+
+/// Convert a scaled factor into the corresponding `max_hash` threshold.
+pub fn max_hash_for_scaled(scaled: u64) -> u64 {
+    match scaled {
+        0 => 0,
+        1 => u64::MAX,
+        _ => (u64::MAX as f64 / scaled as f64).round() as u64,
+    }
+}
+
+/// Convert a `max_hash` threshold back into its scaled factor.
+pub fn scaled_for_max_hash(max_hash: u64) -> u64 {
+    match max_hash {
+        0 => 0,
+        _ => (u64::MAX as f64 / max_hash as f64).round() as u64,
+    }
+}
+
+/// Average nucleotide identity corresponding to a k-mer containment, assuming
+/// mutations are independent: `containment^(1/ksize)`.
+pub fn containment_to_ani(containment: f64, ksize: u32) -> f64 {
+    if containment <= 0.0 || ksize == 0 {
+        return 0.0;
+    }
+    containment.powf(1.0 / f64::from(ksize))
+}
+
+/// Average nucleotide identity corresponding to a k-mer Jaccard similarity,
+/// using the containment of two same-sized sets (`2J / (1 + J)`).
+pub fn jaccard_to_ani(jaccard: f64, ksize: u32) -> f64 {
+    containment_to_ani(2.0 * jaccard / (1.0 + jaccard), ksize)
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct KmerMinHash {
     pub num: u32,
     pub ksize: u32,
     pub is_protein: bool,
+    /// Protein sketch over the Dayhoff alphabet (6 residue classes).
+    pub dayhoff: bool,
+    /// Protein sketch over the hydrophobic-polar alphabet (2 residue classes).
+    pub hp: bool,
+    /// NCBI genetic code used to translate DNA for protein sketches.
+    pub translation_table: u8,
+    /// Not stored in the sketch JSON, but in the `hash_function` field of the
+    /// signature containing it.
+    pub hash_function: HashFunction,
     pub seed: u64,
     pub max_hash: u64,
     pub mins: Vec<u64>,
     pub abunds: Option<Vec<u64>>,
+    /// Cardinality estimate for all hashes added, if enabled with
+    /// `track_cardinality`.
+    pub hll: Option<HyperLogLog>,
 }
 
 impl Default for KmerMinHash {
@@ -51,10 +182,15 @@ impl Default for KmerMinHash {
             num: 1000,
             ksize: 21,
             is_protein: false,
+            dayhoff: false,
+            hp: false,
+            translation_table: 1,
+            hash_function: HashFunction::Murmur64,
             seed: 42,
             max_hash: 0,
             mins: Vec::with_capacity(1000),
             abunds: None,
+            hll: None,
         }
     }
 }
@@ -64,77 +200,140 @@ impl Serialize for KmerMinHash {
     where
         S: Serializer,
     {
-        let n_fields = match &self.abunds {
-            Some(_) => 8,
-            _ => 7,
-        };
-
-        let mut md5_ctx = md5::Context::new();
-        md5_ctx.consume(&self.ksize.to_string());
-        self.mins
-            .iter()
-            .map(|x| md5_ctx.consume(x.to_string()))
-            .count();
+        let mut n_fields = 7;
+        if self.abunds.is_some() {
+            n_fields += 1;
+        }
+        if self.max_hash != 0 {
+            n_fields += 1;
+        }
+        if self.translation_table != 1 {
+            n_fields += 1;
+        }
+        if self.hll.is_some() {
+            n_fields += 1;
+        }
 
         let mut partial = serializer.serialize_struct("KmerMinHash", n_fields)?;
         partial.serialize_field("num", &self.num)?;
         partial.serialize_field("ksize", &self.ksize)?;
         partial.serialize_field("seed", &self.seed)?;
         partial.serialize_field("max_hash", &self.max_hash)?;
+        if self.max_hash != 0 {
+            partial.serialize_field("scaled", &self.scaled())?;
+        }
         partial.serialize_field("mins", &self.mins)?;
 
-        partial.serialize_field("md5sum", &format!("{:x}", md5_ctx.compute()))?;
+        partial.serialize_field("md5sum", &self.md5sum())?;
 
         if let Some(abunds) = &self.abunds {
             partial.serialize_field("abundances", abunds)?;
         }
 
-        partial.serialize_field(
-            "molecule",
-            match &self.is_protein {
-                true => "protein",
-                false => "DNA",
-            },
-        )?;
+        partial.serialize_field("molecule", self.molecule())?;
+        if self.translation_table != 1 {
+            partial.serialize_field("translation_table", &self.translation_table)?;
+        }
+        if let Some(hll) = &self.hll {
+            partial.serialize_field("hll", hll)?;
+        }
 
         partial.end()
     }
 }
 
+fn default_translation_table() -> u8 {
+    1
+}
+
+/// Sketch as stored in signature files, before validation.
+#[derive(Deserialize)]
+pub(crate) struct RawKmerMinHash {
+    num: u32,
+    ksize: u32,
+    seed: u64,
+    #[serde(default)]
+    max_hash: u64,
+    scaled: Option<u64>,
+    md5sum: String,
+    mins: Vec<u64>,
+    abundances: Option<Vec<u64>>,
+    molecule: String,
+    #[serde(default = "default_translation_table")]
+    translation_table: u8,
+    hll: Option<HyperLogLog>,
+}
+
+impl TryFrom<RawKmerMinHash> for KmerMinHash {
+    type Error = Error;
+
+    fn try_from(raw: RawKmerMinHash) -> Result<KmerMinHash, Error> {
+        // max_hash is authoritative; scaled is only used if max_hash is missing
+        let max_hash = match (raw.max_hash, raw.scaled) {
+            (0, Some(scaled)) => max_hash_for_scaled(scaled),
+            (max_hash, _) => max_hash,
+        };
+        let num = if max_hash != 0 { 0 } else { raw.num };
+
+        let (is_protein, dayhoff, hp) = match raw.molecule.to_lowercase().as_ref() {
+            "protein" => (true, false, false),
+            "dayhoff" => (true, true, false),
+            "hp" => (true, false, true),
+            "dna" => (false, false, false),
+            _ => {
+                return Err(SourmashError::InvalidMolecule {
+                    molecule: raw.molecule,
+                }
+                .into());
+            }
+        };
+
+        let mut mh = KmerMinHash {
+            num,
+            ksize: raw.ksize,
+            seed: raw.seed,
+            max_hash,
+            mins: raw.mins,
+            abunds: raw.abundances,
+            is_protein,
+            dayhoff,
+            hp,
+            translation_table: raw.translation_table,
+            hash_function: HashFunction::Murmur64,
+            hll: raw.hll,
+        };
+
+        // older versions of sourmash saved scaled sketches without sorting
+        // the hashes, but computed the md5sum over the sorted hashes. Those
+        // are sorted here; any other unsorted sketch is rejected by validate.
+        if mh.max_hash != 0 && mh.mins.windows(2).any(|w| w[0] > w[1]) {
+            let mut order: Vec<usize> = (0..mh.mins.len()).collect();
+            order.sort_unstable_by_key(|&i| mh.mins[i]);
+
+            let mut sorted = mh.clone();
+            sorted.mins = order.iter().map(|&i| mh.mins[i]).collect();
+            if let Some(abunds) = &mh.abunds {
+                if abunds.len() == mh.mins.len() {
+                    sorted.abunds = Some(order.iter().map(|&i| abunds[i]).collect());
+                }
+            }
+            if sorted.md5sum() == raw.md5sum {
+                mh = sorted;
+            }
+        }
+
+        mh.validate()?;
+        Ok(mh)
+    }
+}
+
 impl<'de> Deserialize<'de> for KmerMinHash {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        #[derive(Deserialize)]
-        struct TempSig {
-            num: u32,
-            ksize: u32,
-            seed: u64,
-            max_hash: u64,
-            md5sum: String,
-            mins: Vec<u64>,
-            abundances: Option<Vec<u64>>,
-            molecule: String,
-        }
-
-        let tmpsig = TempSig::deserialize(deserializer)?;
-
-        let num = if tmpsig.max_hash != 0 { 0 } else { tmpsig.num };
-
-        Ok(KmerMinHash {
-            num,
-            ksize: tmpsig.ksize,
-            seed: tmpsig.seed,
-            max_hash: tmpsig.max_hash,
-            mins: tmpsig.mins,
-            abunds: tmpsig.abundances,
-            is_protein: match tmpsig.molecule.as_ref() {
-                "protein" => true,
-                "DNA" => false,
-                _ => false, // TODO: throw error
-            },
-        })
+        let raw = RawKmerMinHash::deserialize(deserializer)?;
+        KmerMinHash::try_from(raw).map_err(D::Error::custom)
     }
 }
 
@@ -166,10 +365,118 @@ impl KmerMinHash {
             num,
             ksize,
             is_protein,
+            dayhoff: false,
+            hp: false,
+            translation_table: 1,
+            hash_function: HashFunction::Murmur64,
             seed,
             max_hash,
             mins,
             abunds,
+            hll: None,
+        }
+    }
+
+    /// Create a scaled (FracMinHash) sketch, keeping every hash below
+    /// `max_hash_for_scaled(scaled)` instead of a fixed number of hashes.
+    pub fn new_scaled(
+        scaled: u64,
+        ksize: u32,
+        is_protein: bool,
+        seed: u64,
+        track_abundance: bool,
+    ) -> KmerMinHash {
+        KmerMinHash::new(
+            0,
+            ksize,
+            is_protein,
+            seed,
+            max_hash_for_scaled(scaled),
+            track_abundance,
+        )
+    }
+
+    pub fn scaled(&self) -> u64 {
+        scaled_for_max_hash(self.max_hash)
+    }
+
+    pub fn is_scaled(&self) -> bool {
+        self.max_hash != 0
+    }
+
+    /// Select the NCBI genetic code used to translate DNA in `add_sequence`.
+    /// Supported tables are 1-6, 9-14, 16 and 21-26.
+    pub fn set_translation_table(&mut self, table: u8) -> Result<(), Error> {
+        genetic_code(table)?;
+        self.translation_table = table;
+        Ok(())
+    }
+
+    /// Change the hash function. Hashes already in the sketch were computed
+    /// with the old one, so this is only allowed on empty sketches.
+    pub fn set_hash_function(&mut self, hash_function: HashFunction) -> Result<(), Error> {
+        if self.hash_function != hash_function && !self.mins.is_empty() {
+            return Err(SourmashError::NonEmptyMinHash {
+                message: "hash_function".into(),
+            }
+            .into());
+        }
+        self.hash_function = hash_function;
+        Ok(())
+    }
+
+    /// Encode amino acids with the Dayhoff alphabet, which also makes this a
+    /// protein sketch. Like the hash function, it can only change while the
+    /// sketch is empty.
+    pub fn set_dayhoff(&mut self, dayhoff: bool) -> Result<(), Error> {
+        if dayhoff {
+            self.set_alphabet("dayhoff", true, true, false)
+        } else {
+            self.set_alphabet("dayhoff", self.is_protein, false, self.hp)
+        }
+    }
+
+    /// Encode amino acids with the hydrophobic-polar alphabet, see
+    /// `set_dayhoff`.
+    pub fn set_hp(&mut self, hp: bool) -> Result<(), Error> {
+        if hp {
+            self.set_alphabet("hp", true, false, true)
+        } else {
+            self.set_alphabet("hp", self.is_protein, self.dayhoff, false)
+        }
+    }
+
+    fn set_alphabet(
+        &mut self,
+        name: &str,
+        is_protein: bool,
+        dayhoff: bool,
+        hp: bool,
+    ) -> Result<(), Error> {
+        let changed = (is_protein, dayhoff, hp) != (self.is_protein, self.dayhoff, self.hp);
+        if changed && !self.mins.is_empty() {
+            return Err(SourmashError::NonEmptyMinHash {
+                message: name.into(),
+            }
+            .into());
+        }
+        self.is_protein = is_protein;
+        self.dayhoff = dayhoff;
+        self.hp = hp;
+        Ok(())
+    }
+
+    /// The molecule type, as written in signature files: `DNA`, `protein`,
+    /// `dayhoff` or `hp`.
+    pub fn molecule(&self) -> &'static str {
+        if !self.is_protein {
+            "DNA"
+        } else if self.dayhoff {
+            "dayhoff"
+        } else if self.hp {
+            "hp"
+        } else {
+            "protein"
         }
     }
 
@@ -177,7 +484,7 @@ impl KmerMinHash {
         if self.ksize != other.ksize {
             return Err(SourmashError::MismatchKSizes.into());
         }
-        if self.is_protein != other.is_protein {
+        if self.molecule() != other.molecule() {
             return Err(SourmashError::MismatchDNAProt.into());
         }
         if self.max_hash != other.max_hash {
@@ -186,10 +493,17 @@ impl KmerMinHash {
         if self.seed != other.seed {
             return Err(SourmashError::MismatchSeed.into());
         }
+        if self.hash_function != other.hash_function {
+            return Err(SourmashError::MismatchHashFunction.into());
+        }
         Ok(true)
     }
 
     pub fn add_hash(&mut self, hash: u64) {
+        if let Some(hll) = &mut self.hll {
+            hll.add_hash(hash);
+        }
+
         let current_max = match self.mins.last() {
             Some(&x) => x,
             None => u64::max_value(),
@@ -204,10 +518,11 @@ impl KmerMinHash {
                 }
                 return;
             } else if hash <= self.max_hash
-                || current_max > hash
+                || current_max >= hash
                 || (self.mins.len() as u32) < self.num
             {
-                // "good" hash - within range, smaller than current entry, or
+                // "good" hash - within range, not larger than current entry
+                // (so the abundance of the largest hash is still counted), or
                 // still have space available
                 let pos = match self.mins.binary_search(&hash) {
                     Ok(p) => p,
@@ -245,37 +560,22 @@ impl KmerMinHash {
     }
 
     pub fn add_word(&mut self, word: &[u8]) {
-        let hash = _hash_murmur(word, self.seed);
+        let hash = self.hash_function.hash(word, self.seed);
         self.add_hash(hash);
     }
 
     pub fn add_sequence(&mut self, seq: &[u8], force: bool) -> Result<(), Error> {
-        let sequence: Vec<u8> = seq
-            .iter()
-            .map(|&x| (x as char).to_ascii_uppercase() as u8)
-            .collect();
-        if sequence.len() >= (self.ksize as usize) {
+        if seq.len() >= (self.ksize as usize) {
             if !self.is_protein {
                 // dna
-                for kmer in sequence.windows(self.ksize as usize) {
-                    if _checkdna(kmer) {
-                        let rc = revcomp(kmer);
-                        if kmer < &rc {
-                            self.add_word(kmer);
-                        } else {
-                            self.add_word(&rc);
-                        }
-                    } else if !force {
-                        return Err(SourmashError::InvalidDNA {
-                            message: String::from_utf8(kmer.to_vec()).unwrap(),
-                        }
-                        .into());
-                    }
-                }
+                let ksize = self.ksize as usize;
+                for_each_canonical_kmer(seq, ksize, force, |_, kmer| self.add_word(kmer))?;
             } else {
                 // protein
+                let sequence: Vec<u8> = seq.iter().map(u8::to_ascii_uppercase).collect();
                 let rc = revcomp(&sequence);
                 let aa_ksize = self.ksize / 3;
+                let code = genetic_code(self.translation_table)?;
 
                 for i in 0..3 {
                     let substr: Vec<u8> = sequence
@@ -284,7 +584,8 @@ impl KmerMinHash {
                         .skip(i)
                         .take(sequence.len() - i)
                         .collect();
-                    let aa = to_aa(&substr);
+                    let mut aa = to_aa(&substr, code);
+                    self.encode_residues(&mut aa);
 
                     aa.windows(aa_ksize as usize)
                         .map(|n| self.add_word(n))
@@ -292,7 +593,8 @@ impl KmerMinHash {
 
                     let rc_substr: Vec<u8> =
                         rc.iter().cloned().skip(i).take(rc.len() - i).collect();
-                    let aa_rc = to_aa(&rc_substr);
+                    let mut aa_rc = to_aa(&rc_substr, code);
+                    self.encode_residues(&mut aa_rc);
 
                     aa_rc
                         .windows(aa_ksize as usize)
@@ -304,100 +606,167 @@ impl KmerMinHash {
         Ok(())
     }
 
+    /// Add an amino acid sequence directly, without translating it. The
+    /// k-mer size is `ksize / 3`, same as for translated DNA. Only protein
+    /// sketches accept amino acids.
+    pub fn add_protein(&mut self, seq: &[u8]) -> Result<(), Error> {
+        if !self.is_protein {
+            return Err(SourmashError::MismatchDNAProt.into());
+        }
+
+        let mut sequence: Vec<u8> = seq.iter().map(u8::to_ascii_uppercase).collect();
+
+        if let Some(pos) = sequence.iter().position(|aa| !_checkprot(*aa)) {
+            return Err(SourmashError::InvalidProt {
+                message: format!("'{}' at position {}", sequence[pos] as char, pos),
+            }
+            .into());
+        }
+
+        let aa_ksize = (self.ksize / 3) as usize;
+        if aa_ksize == 0 || sequence.len() < aa_ksize {
+            return Ok(());
+        }
+        self.encode_residues(&mut sequence);
+
+        for kmer in sequence.windows(aa_ksize) {
+            self.add_word(kmer);
+        }
+        Ok(())
+    }
+
+    /// Map residues to the reduced alphabet, if this sketch uses one.
+    fn encode_residues(&self, aa: &mut [u8]) {
+        if self.dayhoff {
+            aa.iter_mut().for_each(|r| *r = aa_to_dayhoff(*r));
+        } else if self.hp {
+            aa.iter_mut().for_each(|r| *r = aa_to_hp(*r));
+        }
+    }
+
     pub fn merge(&mut self, other: &KmerMinHash) -> Result<(), Error> {
+        *self = self.union(other)?;
+        Ok(())
+    }
+
+    /// New sketch with the hashes from both sketches. Abundances of hashes
+    /// present in both are added.
+    /// If both sketches estimate their cardinality the estimates are merged.
+    /// If only one does, the hashes kept by the other one are added to it,
+    /// so the result is only a lower bound for the cardinality of the union.
+    pub fn union(&self, other: &KmerMinHash) -> Result<KmerMinHash, Error> {
+        let mut mh = self.combine(other, |_, _| true)?;
+        mh.hll = match (&self.hll, &other.hll) {
+            (Some(hll), Some(other_hll)) => {
+                let mut hll = hll.clone();
+                hll.merge(other_hll)?;
+                Some(hll)
+            }
+            (Some(hll), None) => Some(hll_with(hll, &other.mins)),
+            (None, Some(other_hll)) => Some(hll_with(other_hll, &self.mins)),
+            (None, None) => None,
+        };
+        Ok(mh)
+    }
+
+    /// New sketch with the hashes (and abundances) from this sketch that are
+    /// not in `other`.
+    pub fn difference(&self, other: &KmerMinHash) -> Result<KmerMinHash, Error> {
+        self.combine(other, |in_self, in_other| in_self && !in_other)
+    }
+
+    /// New sketch with the hashes that are only in one of the sketches, with
+    /// the abundances from the sketch they came from.
+    pub fn symmetric_difference(&self, other: &KmerMinHash) -> Result<KmerMinHash, Error> {
+        self.combine(other, |in_self, in_other| in_self != in_other)
+    }
+
+    /// Walk both (sorted) sketches together, keeping the hashes for which
+    /// `keep(in_self, in_other)` is true.
+    fn combine<F>(&self, other: &KmerMinHash, keep: F) -> Result<KmerMinHash, Error>
+    where
+        F: Fn(bool, bool) -> bool,
+    {
         self.check_compatible(other)?;
         let max_size = self.mins.len() + other.mins.len();
-        let mut merged: Vec<u64> = Vec::with_capacity(max_size);
-        let mut merged_abunds: Vec<u64> = Vec::with_capacity(max_size);
-
-        {
-            let mut self_iter = self.mins.iter();
-            let mut other_iter = other.mins.iter();
+        let mut combined: Vec<u64> = Vec::with_capacity(max_size);
+        let mut combined_abunds: Vec<u64> = Vec::with_capacity(max_size);
 
-            let mut self_abunds_iter: Option<std::slice::Iter<'_, u64>>;
-            if let Some(ref mut abunds) = self.abunds {
-                self_abunds_iter = Some(abunds.iter());
-            } else {
-                self_abunds_iter = None;
-            }
+        // sketches without abundance tracking count each hash once
+        let abund = |abunds: &Option<Vec<u64>>, pos: usize| match abunds {
+            Some(abunds) => abunds[pos],
+            None => 1,
+        };
 
-            let mut other_abunds_iter: Option<std::slice::Iter<'_, u64>>;
-            if let Some(ref abunds) = other.abunds {
-                other_abunds_iter = Some(abunds.iter());
-            } else {
-                other_abunds_iter = None;
-            }
-
-            let mut self_value = self_iter.next();
-            let mut other_value = other_iter.next();
-            while self_value.is_some() {
-                let value = self_value.unwrap();
-                match other_value {
-                    None => {
-                        merged.push(*value);
-                        merged.extend(self_iter);
-                        if let Some(sai) = self_abunds_iter {
-                            merged_abunds.extend(sai);
-                        }
-                        break;
-                    }
-                    Some(x) if x < value => {
-                        merged.push(*x);
-                        other_value = other_iter.next();
-
-                        if let Some(ref mut oai) = other_abunds_iter {
-                            if let Some(v) = oai.next() {
-                                merged_abunds.push(*v)
-                            }
-                        }
+        let (mut i, mut j) = (0, 0);
+        while i < self.mins.len() && j < other.mins.len() {
+            match self.mins[i].cmp(&other.mins[j]) {
+                Ordering::Less => {
+                    if keep(true, false) {
+                        combined.push(self.mins[i]);
+                        combined_abunds.push(abund(&self.abunds, i));
                     }
-                    Some(x) if x == value => {
-                        merged.push(*x);
-                        other_value = other_iter.next();
-                        self_value = self_iter.next();
-
-                        if let Some(ref mut oai) = other_abunds_iter {
-                            if let Some(v) = oai.next() {
-                                if let Some(ref mut sai) = self_abunds_iter {
-                                    if let Some(s) = sai.next() {
-                                        merged_abunds.push(*v + *s)
-                                    }
-                                }
-                            }
-                        }
+                    i += 1;
+                }
+                Ordering::Greater => {
+                    if keep(false, true) {
+                        combined.push(other.mins[j]);
+                        combined_abunds.push(abund(&other.abunds, j));
                     }
-                    Some(x) if x > value => {
-                        merged.push(*value);
-                        self_value = self_iter.next();
-
-                        if let Some(ref mut sai) = self_abunds_iter {
-                            if let Some(v) = sai.next() {
-                                merged_abunds.push(*v)
-                            }
-                        }
+                    j += 1;
+                }
+                Ordering::Equal => {
+                    if keep(true, true) {
+                        combined.push(self.mins[i]);
+                        combined_abunds.push(abund(&self.abunds, i) + abund(&other.abunds, j));
                     }
-                    Some(_) => {}
+                    i += 1;
+                    j += 1;
                 }
             }
-            if let Some(value) = other_value {
-                merged.push(*value);
+        }
+        if keep(true, false) {
+            for pos in i..self.mins.len() {
+                combined.push(self.mins[pos]);
+                combined_abunds.push(abund(&self.abunds, pos));
             }
-            merged.extend(other_iter);
-            if let Some(oai) = other_abunds_iter {
-                merged_abunds.extend(oai);
+        }
+        if keep(false, true) {
+            for pos in j..other.mins.len() {
+                combined.push(other.mins[pos]);
+                combined_abunds.push(abund(&other.abunds, pos));
             }
         }
 
-        if merged.len() < (self.num as usize) || (self.num as usize) == 0 {
-            self.mins = merged;
-            self.abunds = Some(merged_abunds);
-        } else {
-            self.mins = merged
-                .iter()
-                .map(|&x| x as u64)
-                .take(self.num as usize)
-                .collect();
-            self.abunds = Some(merged_abunds) // TODO: reduce this one too
+        // scaled sketches keep everything (both sides are already below
+        // max_hash), num sketches keep only the num smallest hashes.
+        if self.num != 0 && combined.len() > (self.num as usize) {
+            combined.truncate(self.num as usize);
+            combined_abunds.truncate(self.num as usize);
+        }
+
+        // the cardinality of the result can't be derived in general, so
+        // copy_empty leaves it out
+        let mut mh = self.copy_empty();
+        mh.mins = combined;
+        if mh.abunds.is_some() {
+            mh.abunds = Some(combined_abunds);
+        }
+        Ok(mh)
+    }
+
+    pub fn remove_hash(&mut self, hash: u64) {
+        if let Ok(pos) = self.mins.binary_search(&hash) {
+            self.mins.remove(pos);
+            if let Some(ref mut abunds) = self.abunds {
+                abunds.remove(pos);
+            }
+        }
+    }
+
+    pub fn remove_many(&mut self, hashes: &[u64]) -> Result<(), Error> {
+        for hash in hashes {
+            self.remove_hash(*hash);
         }
         Ok(())
     }
@@ -438,14 +807,7 @@ impl KmerMinHash {
     pub fn intersection(&self, other: &KmerMinHash) -> Result<(Vec<u64>, u64), Error> {
         self.check_compatible(other)?;
 
-        let mut combined_mh = KmerMinHash::new(
-            self.num,
-            self.ksize,
-            self.is_protein,
-            self.seed,
-            self.max_hash,
-            self.abunds.is_some(),
-        );
+        let mut combined_mh = self.copy_empty();
 
         combined_mh.merge(&self)?;
         combined_mh.merge(&other)?;
@@ -470,14 +832,7 @@ impl KmerMinHash {
     pub fn intersection_size(&self, other: &KmerMinHash) -> Result<(u64, u64), Error> {
         self.check_compatible(other)?;
 
-        let mut combined_mh = KmerMinHash::new(
-            self.num,
-            self.ksize,
-            self.is_protein,
-            self.seed,
-            self.max_hash,
-            self.abunds.is_some(),
-        );
+        let mut combined_mh = self.copy_empty();
 
         combined_mh.merge(&self)?;
         combined_mh.merge(&other)?;
@@ -498,7 +853,20 @@ impl KmerMinHash {
         Ok((it2.count() as u64, combined_mh.mins.len() as u64))
     }
 
+    /// Angular similarity if both sketches track abundances, Jaccard otherwise.
     pub fn compare(&self, other: &KmerMinHash) -> Result<f64, Error> {
+        self.similarity(other, false)
+    }
+
+    pub fn similarity(&self, other: &KmerMinHash, ignore_abundance: bool) -> Result<f64, Error> {
+        if !ignore_abundance && self.abunds.is_some() && other.abunds.is_some() {
+            self.angular_similarity(other)
+        } else {
+            self.jaccard(other)
+        }
+    }
+
+    pub fn jaccard(&self, other: &KmerMinHash) -> Result<f64, Error> {
         self.check_compatible(other)?;
         if let Ok((common, size)) = self.intersection_size(other) {
             return Ok(common as f64 / u64::max(1, size) as f64);
@@ -507,16 +875,361 @@ impl KmerMinHash {
         }
     }
 
+    /// Fraction of the hashes in this sketch that are also in `other`.
+    pub fn contained_by(&self, other: &KmerMinHash) -> Result<f64, Error> {
+        if self.mins.is_empty() {
+            return Ok(0.0);
+        }
+        let common = self.count_common(other)?;
+        Ok(common as f64 / self.mins.len() as f64)
+    }
+
+    /// Containment of the smaller sketch in the larger one.
+    pub fn max_containment(&self, other: &KmerMinHash) -> Result<f64, Error> {
+        let min_size = usize::min(self.mins.len(), other.mins.len());
+        if min_size == 0 {
+            return Ok(0.0);
+        }
+        let common = self.count_common(other)?;
+        Ok(common as f64 / min_size as f64)
+    }
+
+    /// ANI estimated from the containment of this sketch in `other`. For
+    /// protein sketches this is an amino acid identity.
+    pub fn containment_ani(&self, other: &KmerMinHash) -> Result<f64, Error> {
+        Ok(containment_to_ani(
+            self.contained_by(other)?,
+            self.kmer_length(),
+        ))
+    }
+
+    /// ANI estimated from the containment of the smaller sketch in the larger.
+    pub fn max_containment_ani(&self, other: &KmerMinHash) -> Result<f64, Error> {
+        Ok(containment_to_ani(
+            self.max_containment(other)?,
+            self.kmer_length(),
+        ))
+    }
+
+    /// ANI estimated from the Jaccard similarity of both sketches.
+    pub fn jaccard_ani(&self, other: &KmerMinHash) -> Result<f64, Error> {
+        Ok(jaccard_to_ani(self.jaccard(other)?, self.kmer_length()))
+    }
+
+    /// Jaccard similarity with a confidence interval at the `confidence`
+    /// level (e.g. `0.95`).
+    pub fn jaccard_estimate(
+        &self,
+        other: &KmerMinHash,
+        confidence: f64,
+    ) -> Result<Estimate, Error> {
+        let (common, size) = self.intersection_size(other)?;
+        Ok(proportion_estimate(common, size, self.scaled(), confidence))
+    }
+
+    /// Containment of this sketch in `other`, with a confidence interval at
+    /// the `confidence` level. Use `Estimate::to_ani` for the ANI interval.
+    pub fn containment_estimate(
+        &self,
+        other: &KmerMinHash,
+        confidence: f64,
+    ) -> Result<Estimate, Error> {
+        let common = self.count_common(other)?;
+        Ok(proportion_estimate(
+            common,
+            self.mins.len() as u64,
+            self.scaled(),
+            confidence,
+        ))
+    }
+
+    /// Length of the hashed k-mers: residues for protein sketches, bases
+    /// otherwise.
+    pub fn kmer_length(&self) -> u32 {
+        if self.is_protein {
+            self.ksize / 3
+        } else {
+            self.ksize
+        }
+    }
+
+    /// Cosine of the abundance vectors, converted to an angular similarity
+    /// (1 - angle / (pi / 2)). Only hashes in the hash space covered by both
+    /// sketches are used.
+    pub fn angular_similarity(&self, other: &KmerMinHash) -> Result<f64, Error> {
+        self.check_compatible(other)?;
+
+        let (abunds, other_abunds) = match (&self.abunds, &other.abunds) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return Err(SourmashError::NeedsAbundanceTracking.into()),
+        };
+
+        let max_hash = u64::min(self.max_hash_seen(), other.max_hash_seen());
+        let upper = |mins: &[u64]| match mins.binary_search(&max_hash) {
+            Ok(p) => p + 1,
+            Err(p) => p,
+        };
+        let (len, other_len) = (upper(&self.mins), upper(&other.mins));
+
+        let norm = |abunds: &[u64]| {
+            abunds
+                .iter()
+                .map(|&a| (a as f64) * (a as f64))
+                .sum::<f64>()
+                .sqrt()
+        };
+        let (a_norm, b_norm) = (norm(&abunds[..len]), norm(&other_abunds[..other_len]));
+        if a_norm == 0. || b_norm == 0. {
+            return Ok(0.0);
+        }
+
+        let mut prod = 0.;
+        let (mut i, mut j) = (0, 0);
+        while i < len && j < other_len {
+            match self.mins[i].cmp(&other.mins[j]) {
+                Ordering::Less => i += 1,
+                Ordering::Greater => j += 1,
+                Ordering::Equal => {
+                    prod += (abunds[i] as f64) * (other_abunds[j] as f64);
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+
+        let cos = f64::min(prod / (a_norm * b_norm), 1.);
+        let distance = 2. * cos.acos() / std::f64::consts::PI;
+        Ok(1. - distance)
+    }
+
+    /// Largest hash value this sketch is guaranteed to have seen all hashes
+    /// below (a full num sketch stops at its largest min).
+    fn max_hash_seen(&self) -> u64 {
+        match self.mins.last() {
+            Some(&last) if self.num != 0 && self.mins.len() >= self.num as usize => last,
+            _ => u64::MAX,
+        }
+    }
+
+    /// Keep only the `num` smallest hashes.
+    pub fn downsample_num(&self, num: u32) -> Result<KmerMinHash, Error> {
+        if num == 0 || (self.num != 0 && num > self.num) {
+            return Err(SourmashError::InvalidDownsample {
+                message: format!("can't go from num={} to num={}", self.num, num),
+            }
+            .into());
+        }
+
+        let len = usize::min(num as usize, self.mins.len());
+        Ok(self.truncated(len, num, 0))
+    }
+
+    /// Keep only hashes below the threshold for the new `scaled` factor.
+    pub fn downsample_scaled(&self, scaled: u64) -> Result<KmerMinHash, Error> {
+        self.downsample_max_hash(max_hash_for_scaled(scaled))
+    }
+
+    /// Keep only hashes smaller than or equal to `max_hash`.
+    pub fn downsample_max_hash(&self, max_hash: u64) -> Result<KmerMinHash, Error> {
+        if max_hash == 0 || (self.max_hash != 0 && max_hash > self.max_hash) {
+            return Err(SourmashError::InvalidDownsample {
+                message: format!(
+                    "can't go from max_hash={} to max_hash={}",
+                    self.max_hash, max_hash
+                ),
+            }
+            .into());
+        }
+
+        let len = match self.mins.binary_search(&max_hash) {
+            Ok(p) => p + 1,
+            Err(p) => p,
+        };
+
+        // A full num sketch only has all the hashes below max_hash if some
+        // hash was left out because it is above the new threshold.
+        if self.num != 0 && self.mins.len() >= self.num as usize && len == self.mins.len() {
+            return Err(SourmashError::InvalidDownsample {
+                message: format!(
+                    "num={} sketch doesn't have all hashes below max_hash={}",
+                    self.num, max_hash
+                ),
+            }
+            .into());
+        }
+
+        Ok(self.truncated(len, 0, max_hash))
+    }
+
+    /// Keep only hashes with abundance between `min_abundance` and
+    /// `max_abundance` (both inclusive, no upper limit if `None`).
+    pub fn filter_abundance(
+        &self,
+        min_abundance: u64,
+        max_abundance: Option<u64>,
+    ) -> Result<KmerMinHash, Error> {
+        let abunds = match &self.abunds {
+            Some(abunds) => abunds,
+            None => return Err(SourmashError::NeedsAbundanceTracking.into()),
+        };
+        let max_abundance = max_abundance.unwrap_or_else(u64::max_value);
+
+        let mut mh = self.copy_empty();
+        let mut filtered_abunds = Vec::new();
+        for (hash, &abund) in self.mins.iter().zip(abunds) {
+            if abund >= min_abundance && abund <= max_abundance {
+                mh.mins.push(*hash);
+                filtered_abunds.push(abund);
+            }
+        }
+        mh.abunds = Some(filtered_abunds);
+        Ok(mh)
+    }
+
+    /// Number of hashes for each abundance, as `(abundance, count)` pairs
+    /// sorted by abundance.
+    pub fn abundance_histogram(&self) -> Result<Vec<(u64, u64)>, Error> {
+        let mut abunds = match &self.abunds {
+            Some(abunds) => abunds.clone(),
+            None => return Err(SourmashError::NeedsAbundanceTracking.into()),
+        };
+        abunds.sort_unstable();
+
+        let mut histogram: Vec<(u64, u64)> = Vec::new();
+        for abund in abunds {
+            match histogram.last_mut() {
+                Some((last, count)) if *last == abund => *count += 1,
+                _ => histogram.push((abund, 1)),
+            }
+        }
+        Ok(histogram)
+    }
+
+    /// Same hashes, without abundances.
+    pub fn flatten(&self) -> KmerMinHash {
+        let mut mh = self.clone();
+        mh.abunds = None;
+        mh
+    }
+
+    /// An empty sketch with the same parameters as this one. Cardinality
+    /// tracking is not copied, call `track_cardinality` to enable it again.
+    pub fn copy_empty(&self) -> KmerMinHash {
+        let mut mh = self.truncated(0, self.num, self.max_hash);
+        mh.hll = None;
+        mh
+    }
+
+    /// Estimate the number of distinct hashes added to this sketch (and not
+    /// only the ones kept), with a HyperLogLog of precision `p`. Only hashes
+    /// added after this call are counted.
+    pub fn track_cardinality(&mut self, p: u8) -> Result<(), Error> {
+        self.hll = Some(HyperLogLog::new(p)?);
+        Ok(())
+    }
+
+    /// Estimated number of distinct hashes added, if tracked. It is never
+    /// less than the number of hashes kept in the sketch.
+    pub fn cardinality(&self) -> Option<u64> {
+        self.hll
+            .as_ref()
+            .map(|hll| hll.cardinality().max(self.mins.len() as u64))
+    }
+
+    fn truncated(&self, len: usize, num: u32, max_hash: u64) -> KmerMinHash {
+        KmerMinHash {
+            num,
+            ksize: self.ksize,
+            is_protein: self.is_protein,
+            dayhoff: self.dayhoff,
+            hp: self.hp,
+            translation_table: self.translation_table,
+            hash_function: self.hash_function,
+            seed: self.seed,
+            max_hash,
+            mins: self.mins[..len].to_vec(),
+            abunds: self.abunds.as_ref().map(|abunds| abunds[..len].to_vec()),
+            hll: self.hll.clone(),
+        }
+    }
+
+    /// Check that the sketch is consistent: hashes sorted and unique, one
+    /// abundance per hash, no more hashes than `num` and none above
+    /// `max_hash`, and a known translation table.
+    pub fn validate(&self) -> Result<(), Error> {
+        if let Some(position) = self.mins.windows(2).position(|w| w[0] >= w[1]) {
+            return Err(SourmashError::UnsortedHashes {
+                position: position + 1,
+            }
+            .into());
+        }
+
+        if let Some(abunds) = &self.abunds {
+            if abunds.len() != self.mins.len() {
+                return Err(SourmashError::InvalidAbundances {
+                    hashes: self.mins.len(),
+                    abundances: abunds.len(),
+                }
+                .into());
+            }
+        }
+
+        if self.num != 0 && self.mins.len() > self.num as usize {
+            return Err(SourmashError::InvalidNum {
+                num: self.num,
+                hashes: self.mins.len(),
+            }
+            .into());
+        }
+
+        if self.max_hash != 0 {
+            if let Some(&hash) = self.mins.last() {
+                if hash > self.max_hash {
+                    return Err(SourmashError::InvalidMaxHash {
+                        hash,
+                        max_hash: self.max_hash,
+                    }
+                    .into());
+                }
+            }
+        }
+
+        genetic_code(self.translation_table)?;
+        Ok(())
+    }
+
+    /// Checksum of the k-mer size and hashes, stored as `md5sum` in
+    /// signature files.
+    pub fn md5sum(&self) -> String {
+        let mut md5_ctx = md5::Context::new();
+        md5_ctx.consume(&self.ksize.to_string());
+        self.mins
+            .iter()
+            .map(|x| md5_ctx.consume(x.to_string()))
+            .count();
+        format!("{:x}", md5_ctx.compute())
+    }
+
     pub fn size(&self) -> usize {
         self.mins.len()
     }
 }
 
-struct Intersection<T, I: Iterator<Item = T>> {
+/// Items present in both sorted iterators.
+pub struct Intersection<T, I: Iterator<Item = T>> {
     left: Peekable<I>,
     right: Peekable<I>,
 }
 
+impl<T, I: Iterator<Item = T>> Intersection<T, I> {
+    pub fn new(left: I, right: I) -> Self {
+        Intersection {
+            left: left.peekable(),
+            right: right.peekable(),
+        }
+    }
+}
+
 impl<T: Ord, I: Iterator<Item = T>> Iterator for Intersection<T, I> {
     type Item = T;
 
@@ -543,27 +1256,179 @@ impl<T: Ord, I: Iterator<Item = T>> Iterator for Intersection<T, I> {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Items present in any of the sorted iterators, without repetitions.
+pub struct Union<T, I: Iterator<Item = T>> {
+    left: Peekable<I>,
+    right: Peekable<I>,
+}
+
+impl<T, I: Iterator<Item = T>> Union<T, I> {
+    pub fn new(left: I, right: I) -> Self {
+        Union {
+            left: left.peekable(),
+            right: right.peekable(),
+        }
+    }
+}
+
+impl<T: Ord, I: Iterator<Item = T>> Iterator for Union<T, I> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let res = match (self.left.peek(), self.right.peek()) {
+            (Some(ref left_key), Some(ref right_key)) => left_key.cmp(right_key),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => return None,
+        };
+
+        match res {
+            Ordering::Less => self.left.next(),
+            Ordering::Greater => self.right.next(),
+            Ordering::Equal => {
+                self.right.next();
+                self.left.next()
+            }
+        }
+    }
+}
+
+/// Items from the left sorted iterator that are not in the right one.
+pub struct Difference<T, I: Iterator<Item = T>> {
+    left: Peekable<I>,
+    right: Peekable<I>,
+}
+
+impl<T, I: Iterator<Item = T>> Difference<T, I> {
+    pub fn new(left: I, right: I) -> Self {
+        Difference {
+            left: left.peekable(),
+            right: right.peekable(),
+        }
+    }
+}
+
+impl<T: Ord, I: Iterator<Item = T>> Iterator for Difference<T, I> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        loop {
+            let res = match (self.left.peek(), self.right.peek()) {
+                (Some(ref left_key), Some(ref right_key)) => left_key.cmp(right_key),
+                (Some(_), None) => Ordering::Less,
+                (None, _) => return None,
+            };
+
+            match res {
+                Ordering::Less => return self.left.next(),
+                Ordering::Greater => {
+                    self.right.next();
+                }
+                Ordering::Equal => {
+                    self.left.next();
+                    self.right.next();
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Signature {
-    #[serde(default = "default_class")]
     pub class: String,
 
-    #[serde(default)]
     pub email: String,
+    /// Only used for signatures without sketches, otherwise the hash
+    /// function of the sketches is saved (see `hash_function_name`).
     pub hash_function: String,
 
     pub filename: Option<String>,
     pub name: Option<String>,
 
-    #[serde(default = "default_license")]
     pub license: String,
 
     pub signatures: Vec<KmerMinHash>,
 
-    #[serde(default = "default_version")]
     pub version: f64,
 }
 
+/// Signature as stored in signature files, before validation.
+#[derive(Deserialize)]
+pub(crate) struct RawSignature {
+    #[serde(default = "default_class")]
+    class: String,
+    #[serde(default)]
+    email: String,
+    hash_function: String,
+    filename: Option<String>,
+    name: Option<String>,
+    #[serde(default = "default_license")]
+    license: String,
+    signatures: Vec<RawKmerMinHash>,
+    #[serde(default = "default_version")]
+    version: f64,
+}
+
+impl TryFrom<RawSignature> for Signature {
+    type Error = Error;
+
+    fn try_from(raw: RawSignature) -> Result<Signature, Error> {
+        let hash_function = HashFunction::from_str(&raw.hash_function)?;
+        let signatures = raw
+            .signatures
+            .into_iter()
+            .map(|raw_mh| {
+                let mut mh = KmerMinHash::try_from(raw_mh)?;
+                mh.hash_function = hash_function;
+                Ok(mh)
+            })
+            .collect::<Result<Vec<KmerMinHash>, Error>>()?;
+
+        Ok(Signature {
+            class: raw.class,
+            email: raw.email,
+            hash_function: raw.hash_function,
+            filename: raw.filename,
+            name: raw.name,
+            license: raw.license,
+            signatures,
+            version: raw.version,
+        })
+    }
+}
+
+impl Serialize for Signature {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let hash_function = self
+            .hash_function_name()
+            .map_err(serde::ser::Error::custom)?;
+
+        let mut partial = serializer.serialize_struct("Signature", 8)?;
+        partial.serialize_field("class", &self.class)?;
+        partial.serialize_field("email", &self.email)?;
+        partial.serialize_field("hash_function", hash_function)?;
+        partial.serialize_field("filename", &self.filename)?;
+        partial.serialize_field("name", &self.name)?;
+        partial.serialize_field("license", &self.license)?;
+        partial.serialize_field("signatures", &self.signatures)?;
+        partial.serialize_field("version", &self.version)?;
+        partial.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Signature {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = RawSignature::deserialize(deserializer)?;
+        Signature::try_from(raw).map_err(D::Error::custom)
+    }
+}
+
 fn default_license() -> String {
     "CC0".to_string()
 }
@@ -577,17 +1442,128 @@ fn default_version() -> f64 {
 }
 
 impl Signature {
+    /// Name of the hash function used by the sketches, as stored in
+    /// signature files. All sketches in a signature share the same
+    /// `hash_function` field, so mixing hash functions is an error.
+    pub fn hash_function_name(&self) -> Result<&str, Error> {
+        let mut sketches = self.signatures.iter();
+        let first = match sketches.next() {
+            Some(mh) => mh.hash_function,
+            None => return Ok(&self.hash_function),
+        };
+        if sketches.any(|mh| mh.hash_function != first) {
+            return Err(SourmashError::MismatchHashFunction.into());
+        }
+        Ok(first.as_str())
+    }
+
+    /// Load signatures from a file, which can be compressed with gzip, bzip2
+    /// or xz.
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Vec<Signature>, Error> {
         let mut reader = io::BufReader::new(File::open(path)?);
         Ok(Signature::from_reader(&mut reader)?)
     }
 
+    /// Load signatures in JSON or in the binary format (see `binary`),
+    /// detected from the start of the input. Compressed input is
+    /// decompressed first.
     pub fn from_reader<R>(rdr: &mut R) -> Result<Vec<Signature>, Error>
     where
         R: io::Read,
     {
-        let sigs: Vec<Signature> = serde_json::from_reader(rdr)?;
-        Ok(sigs)
+        let (rdr, _) = get_decoder(rdr)?;
+        let (is_binary, mut rdr) = binary::detect(rdr)?;
+        if is_binary {
+            return binary::read_signatures(&mut rdr);
+        }
+
+        let raw: Vec<RawSignature> = serde_json::from_reader(rdr)?;
+        raw.into_iter().map(Signature::try_from).collect()
+    }
+
+    /// Save signatures as JSON to `path` (`-` for stdout), compressed if
+    /// the extension is `.gz`, `.bz2` or `.xz`.
+    pub fn save_path<P: AsRef<Path>>(sigs: &[Signature], path: P) -> Result<(), Error> {
+        let compression = compression_from_extension(&path);
+        Signature::save_path_compressed(sigs, path, compression)
+    }
+
+    /// Same as `save_path`, ignoring the extension and using `compression`.
+    pub fn save_path_compressed<P: AsRef<Path>>(
+        sigs: &[Signature],
+        path: P,
+        compression: CompressionFormat,
+    ) -> Result<(), Error> {
+        let mut wtr = get_output(path, compression)?;
+        Signature::save_signatures(&mut wtr, sigs)?;
+        wtr.finish()?;
+        Ok(())
+    }
+
+    /// Write `sigs` as a JSON array. To write signatures as they are
+    /// produced, use `stream::SignatureWriter`.
+    pub fn save_signatures<W: io::Write>(wtr: &mut W, sigs: &[Signature]) -> Result<(), Error> {
+        serde_json::to_writer(&mut *wtr, sigs)?;
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Same as `from_reader`, but fails if the `md5sum` stored for a sketch
+    /// doesn't match its content. The binary format doesn't store md5sums,
+    /// so binary input is loaded as in `from_reader`.
+    pub fn from_reader_checked<R>(rdr: &mut R) -> Result<Vec<Signature>, Error>
+    where
+        R: io::Read,
+    {
+        let (rdr, _) = get_decoder(rdr)?;
+        let (is_binary, mut rdr) = binary::detect(rdr)?;
+        if is_binary {
+            return binary::read_signatures(&mut rdr);
+        }
+
+        let raw: Vec<RawSignature> = serde_json::from_reader(rdr)?;
+        raw.into_iter().map(Signature::try_from_checked).collect()
+    }
+
+    /// `Signature::try_from`, failing if a stored md5sum doesn't match.
+    pub(crate) fn try_from_checked(raw: RawSignature) -> Result<Signature, Error> {
+        let stored: Vec<String> = raw.signatures.iter().map(|mh| mh.md5sum.clone()).collect();
+        let sig = Signature::try_from(raw)?;
+
+        for (mh, stored) in sig.signatures.iter().zip(stored) {
+            let computed = mh.md5sum();
+            if computed != stored {
+                return Err(SourmashError::InvalidMd5sum { stored, computed }.into());
+            }
+        }
+        Ok(sig)
+    }
+
+    /// Remove signatures with the same sketches (by md5sum) as an earlier
+    /// one, keeping the order of the first occurrences.
+    pub fn dedup<I>(sigs: I) -> Vec<Signature>
+    where
+        I: IntoIterator<Item = Signature>,
+    {
+        let mut seen = HashSet::new();
+        sigs.into_iter()
+            .filter(|sig| {
+                let md5sums: Vec<String> = sig.signatures.iter().map(KmerMinHash::md5sum).collect();
+                seen.insert(md5sums)
+            })
+            .collect()
+    }
+
+    /// Load signatures, split into one signature per sketch, and keep the
+    /// sketches matching `selection`. Signatures are read one at a time, so
+    /// sketches that are not selected are never all in memory.
+    pub fn load_selected<R>(buf: &mut R, selection: &Selection) -> Result<Vec<Signature>, Error>
+    where
+        R: io::Read,
+    {
+        SignatureIter::new(buf)?
+            .with_selection(selection.clone())
+            .collect()
     }
 
     pub fn load_signatures<R>(
@@ -599,49 +1575,56 @@ impl Signature {
     where
         R: io::Read,
     {
-        let orig_sigs = Signature::from_reader(buf)?;
+        let selection = Selection {
+            ksize: if ksize == 0 { None } else { Some(ksize as u32) },
+            moltype: moltype.map(String::from),
+            ..Default::default()
+        };
+        let mut sigs = Signature::load_selected(buf, &selection)?;
+
+        // downsample scaled sketches with a finer resolution than requested,
+        // so they can be compared with each other.
+        if let Some(scaled) = scaled.filter(|&s| s != 0) {
+            for sig in &mut sigs {
+                for mh in &mut sig.signatures {
+                    if mh.is_scaled() && mh.scaled() < scaled {
+                        *mh = mh.downsample_scaled(scaled)?;
+                    }
+                }
+            }
+        }
 
-        let flat_sigs = orig_sigs.into_iter().flat_map(|s| {
-            s.signatures
-                .iter()
-                .map(|mh| {
-                    let mut new_s = s.clone();
-                    new_s.signatures = vec![mh.clone()];
-                    new_s
-                })
-                .collect::<Vec<Signature>>()
-        });
-
-        let filtered_sigs = flat_sigs.filter_map(|mut sig| {
-            let good_mhs: Vec<KmerMinHash> = sig
-                .signatures
-                .into_iter()
-                .filter(|mh| {
-                    if ksize == 0 || ksize == mh.ksize as usize {
-                        match moltype {
-                            Some(x) => {
-                                if (x.to_lowercase() == "dna" && !mh.is_protein)
-                                    || (x.to_lowercase() == "protein" && mh.is_protein)
-                                {
-                                    return true;
-                                }
-                            }
-                            None => return true,
-                        };
-                    };
-                    false
-                })
-                .collect();
+        Ok(sigs)
+    }
 
-            if good_mhs.is_empty() {
-                return None;
-            };
+    pub fn downsample_num(&self, num: u32) -> Result<Signature, Error> {
+        let mut sig = self.clone();
+        sig.signatures = self
+            .signatures
+            .iter()
+            .map(|mh| mh.downsample_num(num))
+            .collect::<Result<Vec<KmerMinHash>, Error>>()?;
+        Ok(sig)
+    }
 
-            sig.signatures = good_mhs;
-            Some(sig)
-        });
+    pub fn downsample_scaled(&self, scaled: u64) -> Result<Signature, Error> {
+        let mut sig = self.clone();
+        sig.signatures = self
+            .signatures
+            .iter()
+            .map(|mh| mh.downsample_scaled(scaled))
+            .collect::<Result<Vec<KmerMinHash>, Error>>()?;
+        Ok(sig)
+    }
 
-        Ok(filtered_sigs.collect())
+    pub fn downsample_max_hash(&self, max_hash: u64) -> Result<Signature, Error> {
+        let mut sig = self.clone();
+        sig.signatures = self
+            .signatures
+            .iter()
+            .map(|mh| mh.downsample_max_hash(max_hash))
+            .collect::<Result<Vec<KmerMinHash>, Error>>()?;
+        Ok(sig)
     }
 }
 
@@ -674,6 +1657,13 @@ impl PartialEq for Signature {
     }
 }
 
+/// A copy of `hll` with `hashes` added to it.
+fn hll_with(hll: &HyperLogLog, hashes: &[u64]) -> HyperLogLog {
+    let mut hll = hll.clone();
+    hashes.iter().for_each(|&hash| hll.add_hash(hash));
+    hll
+}
+
 #[inline]
 fn revcomp(seq: &[u8]) -> Vec<u8> {
     seq.iter()
@@ -688,117 +1678,160 @@ fn revcomp(seq: &[u8]) -> Vec<u8> {
         .collect()
 }
 
-lazy_static! {
-    static ref CODONTABLE: HashMap<&'static str, u8> = {
-        let mut m = HashMap::new();
-
-        m.insert("TTT", b'F');
-        m.insert("TTC", b'F');
-        m.insert("TTA", b'L');
-        m.insert("TTG", b'L');
-
-        m.insert("TCT", b'S');
-        m.insert("TCC", b'S');
-        m.insert("TCA", b'S');
-        m.insert("TCG", b'S');
-
-        m.insert("TAT", b'Y');
-        m.insert("TAC", b'Y');
-        m.insert("TAA", b'*');
-        m.insert("TAG", b'*');
-
-        m.insert("TGT", b'C');
-        m.insert("TGC", b'C');
-        m.insert("TGA", b'*');
-        m.insert("TGG", b'W');
-
-        m.insert("CTT", b'L');
-        m.insert("CTC", b'L');
-        m.insert("CTA", b'L');
-        m.insert("CTG", b'L');
-
-        m.insert("CCT", b'P');
-        m.insert("CCC", b'P');
-        m.insert("CCA", b'P');
-        m.insert("CCG", b'P');
-
-        m.insert("CAT", b'H');
-        m.insert("CAC", b'H');
-        m.insert("CAA", b'Q');
-        m.insert("CAG", b'Q');
-
-        m.insert("CGT", b'R');
-        m.insert("CGC", b'R');
-        m.insert("CGA", b'R');
-        m.insert("CGG", b'R');
-
-        m.insert("ATT", b'I');
-        m.insert("ATC", b'I');
-        m.insert("ATA", b'I');
-        m.insert("ATG", b'M');
-
-        m.insert("ACT", b'T');
-        m.insert("ACC", b'T');
-        m.insert("ACA", b'T');
-        m.insert("ACG", b'T');
-
-        m.insert("AAT", b'N');
-        m.insert("AAC", b'N');
-        m.insert("AAA", b'K');
-        m.insert("AAG", b'K');
-
-        m.insert("AGT", b'S');
-        m.insert("AGC", b'S');
-        m.insert("AGA", b'R');
-        m.insert("AGG", b'R');
-
-        m.insert("GTT", b'V');
-        m.insert("GTC", b'V');
-        m.insert("GTA", b'V');
-        m.insert("GTG", b'V');
-
-        m.insert("GCT", b'A');
-        m.insert("GCC", b'A');
-        m.insert("GCA", b'A');
-        m.insert("GCG", b'A');
-
-        m.insert("GAT", b'D');
-        m.insert("GAC", b'D');
-        m.insert("GAA", b'E');
-        m.insert("GAG", b'E');
-
-        m.insert("GGT", b'G');
-        m.insert("GGC", b'G');
-        m.insert("GGA", b'G');
-        m.insert("GGG", b'G');
-
-        m
-    };
+/// NCBI genetic codes, as the amino acid for each of the 64 codons in TCAG
+/// order (`TTT`, `TTC`, `TTA`, `TTG`, `TCT`, ...).
+fn genetic_code(table: u8) -> Result<&'static [u8; 64], Error> {
+    Ok(match table {
+        1 | 11 => b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        2 => b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSS**VVVVAAAADDEEGGGG",
+        3 => b"FFLLSSSSYY**CCWWTTTTPPPPHHQQRRRRIIMMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        4 => b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        5 => b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSSSVVVVAAAADDEEGGGG",
+        6 => b"FFLLSSSSYYQQCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        9 => b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        10 => b"FFLLSSSSYY**CCCWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        12 => b"FFLLSSSSYY**CC*WLLLSPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        13 => b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSGGVVVVAAAADDEEGGGG",
+        14 => b"FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        16 => b"FFLLSSSSYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        21 => b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        22 => b"FFLLSS*SYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        23 => b"FF*LSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        24 => b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSSKVVVVAAAADDEEGGGG",
+        25 => b"FFLLSSSSYY**CCGWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        26 => b"FFLLSSSSYY**CC*WLLLAPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        _ => return Err(SourmashError::InvalidTranslationTable { table }.into()),
+    })
 }
 
+/// Translate `seq` (uppercase) in a single frame. Codons with anything other
+/// than ACGT are translated to `X`, and a trailing partial codon is ignored.
 #[inline]
-fn to_aa(seq: &[u8]) -> Vec<u8> {
-    let mut converted: Vec<u8> = Vec::with_capacity(seq.len() / 3);
+fn to_aa(seq: &[u8], code: &[u8; 64]) -> Vec<u8> {
+    seq.chunks_exact(3)
+        .map(|codon| {
+            let mut idx = 0;
+            for base in codon {
+                idx = idx * 4
+                    + match base {
+                        b'T' => 0,
+                        b'C' => 1,
+                        b'A' => 2,
+                        b'G' => 3,
+                        _ => return b'X',
+                    };
+            }
+            code[idx]
+        })
+        .collect()
+}
 
-    for chunk in seq.chunks(3) {
-        if chunk.len() != 3 {
-            break;
-        }
-        if let Some(codon) = CODONTABLE.get(str::from_utf8(chunk).unwrap()) {
-            converted.push(*codon);
-        }
+/// Dayhoff encoding: groups residues in 6 classes (`a`-`f`) by their
+/// substitution patterns. Anything else is mapped to `X`.
+#[inline]
+fn aa_to_dayhoff(aa: u8) -> u8 {
+    match aa {
+        b'C' => b'a',
+        b'A' | b'G' | b'P' | b'S' | b'T' => b'b',
+        b'D' | b'E' | b'N' | b'Q' => b'c',
+        b'H' | b'K' | b'R' => b'd',
+        b'I' | b'L' | b'M' | b'V' => b'e',
+        b'F' | b'W' | b'Y' => b'f',
+        _ => b'X',
     }
+}
 
-    converted
+/// Hydrophobic-polar encoding: `h` for hydrophobic residues, `p` for polar
+/// ones. Anything else is mapped to `X`.
+#[inline]
+fn aa_to_hp(aa: u8) -> u8 {
+    match aa {
+        b'A' | b'F' | b'G' | b'I' | b'L' | b'M' | b'P' | b'V' | b'W' | b'Y' => b'h',
+        b'C' | b'D' | b'E' | b'H' | b'K' | b'N' | b'Q' | b'R' | b'S' | b'T' => b'p',
+        _ => b'X',
+    }
 }
 
+/// The 20 standard amino acids, the ambiguity codes B/J/X/Z, selenocysteine
+/// (U), pyrrolysine (O) and stop codons (*).
 #[inline]
-fn _checkdna(seq: &[u8]) -> bool {
-    for n in seq.iter() {
-        match *n as char {
-            'A' | 'a' | 'C' | 'c' | 'G' | 'g' | 'T' | 't' => (),
-            _ => return false,
+fn _checkprot(aa: u8) -> bool {
+    b"ACDEFGHIKLMNPQRSTVWYBJOUXZ*".contains(&aa)
+}
+
+/// Calls `f` with the position and canonical form (the smallest of the k-mer
+/// and its reverse complement, uppercased) of every k-mer in `seq`.
+///
+/// Both strands are kept in rolling buffers, so each base is only processed
+/// once. K-mers containing bases other than ACGT are skipped if `force` is
+/// set, otherwise an `InvalidDNA` error is raised for the first one.
+pub fn for_each_canonical_kmer<F>(
+    seq: &[u8],
+    ksize: usize,
+    force: bool,
+    mut f: F,
+) -> Result<(), Error>
+where
+    F: FnMut(usize, &[u8]),
+{
+    if ksize == 0 || seq.len() < ksize {
+        return Ok(());
+    }
+
+    // The forward strand grows to the right and the reverse complement to
+    // the left. When a buffer is full the last k - 1 bases are moved back
+    // to the other end, so the current k-mer is always contiguous.
+    let capacity = usize::max(4 * ksize, 1024);
+    let mut fwd = vec![0u8; capacity];
+    let mut rc = vec![0u8; capacity];
+    let mut fwd_end = 0;
+    let mut rc_start = capacity;
+    let mut valid = 0;
+
+    for (pos, base) in seq.iter().enumerate() {
+        let (base, complement) = match base {
+            b'A' | b'a' => (b'A', b'T'),
+            b'C' | b'c' => (b'C', b'G'),
+            b'G' | b'g' => (b'G', b'C'),
+            b'T' | b't' => (b'T', b'A'),
+            _ => {
+                if !force {
+                    let start = (pos + 1).saturating_sub(ksize);
+                    let kmer: Vec<u8> = seq[start..start + ksize]
+                        .iter()
+                        .map(u8::to_ascii_uppercase)
+                        .collect();
+                    return Err(SourmashError::InvalidDNA {
+                        message: String::from_utf8_lossy(&kmer).into_owned(),
+                    }
+                    .into());
+                }
+                valid = 0;
+                continue;
+            }
+        };
+
+        if fwd_end == capacity {
+            fwd.copy_within(capacity - (ksize - 1).., 0);
+            fwd_end = ksize - 1;
+        }
+        fwd[fwd_end] = base;
+        fwd_end += 1;
+
+        if rc_start == 0 {
+            rc.copy_within(..ksize - 1, capacity - (ksize - 1));
+            rc_start = capacity - (ksize - 1);
+        }
+        rc_start -= 1;
+        rc[rc_start] = complement;
+
+        valid += 1;
+        if valid >= ksize {
+            let kmer = &fwd[fwd_end - ksize..fwd_end];
+            let rc_kmer = &rc[rc_start..rc_start + ksize];
+            f(pos + 1 - ksize, if kmer < rc_kmer { kmer } else { rc_kmer });
         }
     }
-    true
+
+    Ok(())
 }
@@ -7,14 +7,25 @@ use clap::{load_yaml, App};
 use exitfailure::ExitFailure;
 use failure::{Error, ResultExt};
 use human_panic::setup_panic;
+use lazy_init::Lazy;
 use log::{debug, error, info, LevelFilter};
 
+use sourmash::compute::{SignatureBuilder, SketchParams};
 use sourmash::index::nodegraph::Nodegraph;
 use sourmash::index::sbt::{scaffold, Node, MHBT, SBT};
-use sourmash::index::search::search_minhashes;
-use sourmash::index::{Index, Leaf, LeafBuilder};
+use sourmash::index::search::{
+    search_minhashes, search_minhashes_containment, search_minhashes_ignore_abundance,
+    search_minhashes_max_containment,
+};
+use sourmash::index::storage::ReadData;
+use sourmash::index::{Comparable, Index, Leaf, LeafBuilder};
+use sourmash::selection::{Pattern, Picklist, Selection};
+use sourmash::stats::Estimate;
+use sourmash::stream::SignatureWriter;
 use sourmash::Signature;
 
+type SearchFn = fn(&dyn Comparable<Leaf<Signature>>, &Leaf<Signature>, f64) -> bool;
+
 struct Query<T> {
     data: T,
 }
@@ -27,23 +38,37 @@ impl Query<Signature> {
 
     fn moltype(&self) -> String {
         // TODO: this might panic
-        if self.data.signatures[0].is_protein {
-            "protein".into()
-        } else {
-            "DNA".into()
-        }
+        self.data.signatures[0].molecule().into()
     }
 
     fn name(&self) -> String {
-        self.data.name.clone().unwrap()
+        display_name(&self.data)
     }
 }
 
+/// Name of the signature, or its filename or the md5sum of its first sketch
+/// if it doesn't have one.
+fn display_name(sig: &Signature) -> String {
+    sig.name
+        .clone()
+        .or_else(|| sig.filename.clone())
+        .or_else(|| sig.signatures.first().map(|mh| mh.md5sum()))
+        .unwrap_or_default()
+}
+
 impl From<Query<Signature>> for Leaf<Signature> {
     fn from(other: Query<Signature>) -> Leaf<Signature> {
-        let leaf = LeafBuilder::default().build().unwrap();
-        //leaf.data.get_or_create(|| data.query);
-        leaf
+        // the data is already loaded, so the leaf never needs storage
+        let data = Lazy::new();
+        data.get_or_create(|| other.data);
+        LeafBuilder::default()
+            .filename(String::new())
+            .name(String::new())
+            .metadata(String::new())
+            .storage(None)
+            .data(Rc::new(data))
+            .build()
+            .unwrap()
     }
 }
 
@@ -52,9 +77,18 @@ fn load_query_signature(
     ksize: usize,
     moltype: Option<&str>,
     scaled: Option<u64>,
+    selection: &Selection,
 ) -> Result<Query<Signature>, Error> {
     let mut reader = io::BufReader::new(File::open(query)?);
-    let sigs = Signature::load_signatures(&mut reader, ksize, moltype, scaled)?;
+    let sigs = selection.select(Signature::load_signatures(
+        &mut reader,
+        ksize,
+        moltype,
+        scaled,
+    )?);
+    if sigs.is_empty() {
+        return Err(failure::err_msg("no query signature matches the selectors"));
+    }
 
     debug!("{:?}", sigs);
     // TODO: what if we have more than one left?
@@ -119,9 +153,29 @@ fn load_sbts_and_sigs(
     Ok(dbs)
 }
 
+/// Confidence level for the intervals reported by search.
+const CONFIDENCE: f64 = 0.95;
+
 struct Results {
     similarity: f64,
     match_sig: Signature,
+    /// Confidence interval for `similarity`, if it was calculated.
+    estimate: Option<Estimate>,
+}
+
+impl Results {
+    fn to_row(&self) -> String {
+        let interval = match self.estimate {
+            Some(est) => format!("  [{:.1}-{:.1}%]", est.low * 100., est.high * 100.),
+            None => String::new(),
+        };
+        format!(
+            "{:>6.1}%{}       {:60}",
+            self.similarity * 100.,
+            interval,
+            display_name(&self.match_sig)
+        )
+    }
 }
 
 fn search_databases(
@@ -129,21 +183,143 @@ fn search_databases(
     databases: &[Database],
     threshold: f64,
     containment: bool,
+    max_containment: bool,
     best_only: bool,
     ignore_abundance: bool,
 ) -> Result<Vec<Results>, Error> {
     let mut results = Vec::default();
 
-    let search_fn = search_minhashes;
+    let search_fn: SearchFn = if max_containment {
+        search_minhashes_max_containment
+    } else if containment {
+        search_minhashes_containment
+    } else if ignore_abundance {
+        search_minhashes_ignore_abundance
+    } else {
+        search_minhashes
+    };
+    let query_mh = query.data.signatures[0].clone();
     let query_leaf = query.into();
 
     for db in databases {
-        for dataset in db.data.find(search_fn, &query_leaf, threshold) {}
+        let storage = db.data.storage();
+        for dataset in db.data.find(search_fn, &query_leaf, threshold)? {
+            let match_sig: &Signature = dataset.data(&*storage)?;
+            let match_mh = &match_sig.signatures[0];
+
+            let (similarity, estimate) = if max_containment {
+                (query_mh.max_containment(match_mh)?, None)
+            } else if containment {
+                (
+                    query_mh.contained_by(match_mh)?,
+                    Some(query_mh.containment_estimate(match_mh, CONFIDENCE)?),
+                )
+            } else if ignore_abundance || query_mh.abunds.is_none() || match_mh.abunds.is_none() {
+                (
+                    query_mh.jaccard(match_mh)?,
+                    Some(query_mh.jaccard_estimate(match_mh, CONFIDENCE)?),
+                )
+            } else {
+                // there's no interval for the angular similarity
+                (query_mh.angular_similarity(match_mh)?, None)
+            };
+
+            results.push(Results {
+                similarity,
+                match_sig: match_sig.clone(),
+                estimate,
+            });
+        }
     }
 
+    results.sort_by(|a, b| b.similarity.partial_cmp(&a.similarity).unwrap());
+    if best_only {
+        results.truncate(1);
+    }
     Ok(results)
 }
 
+fn sketch_params(cmd: &clap::ArgMatches) -> Result<Vec<SketchParams>, Error> {
+    let scaled: u64 = cmd.value_of("scaled").unwrap().parse()?;
+    let num = if scaled != 0 {
+        0
+    } else {
+        cmd.value_of("num-hashes").unwrap().parse()?
+    };
+    let track_abundance = cmd.is_present("track-abundance");
+
+    let mut moltypes = Vec::new();
+    if !cmd.is_present("no-dna") {
+        moltypes.push("dna");
+    }
+    for moltype in &["protein", "dayhoff", "hp"] {
+        if cmd.is_present(moltype) {
+            moltypes.push(moltype);
+        }
+    }
+
+    let mut params = Vec::new();
+    for ksize in cmd.value_of("ksizes").unwrap().split(',') {
+        let ksize = ksize
+            .trim()
+            .parse::<u32>()
+            .context(format!("invalid k-mer size: {}", ksize))?;
+        for moltype in &moltypes {
+            params.push(SketchParams::new(
+                ksize,
+                moltype,
+                num,
+                scaled,
+                track_abundance,
+            ));
+        }
+    }
+
+    if params.is_empty() {
+        return Err(failure::err_msg("no signatures to compute"));
+    }
+    Ok(params)
+}
+
+fn selection_from_args(cmd: &clap::ArgMatches) -> Result<Selection, Error> {
+    let mut selection = Selection::default();
+    if let Some(ksize) = cmd.value_of("ksize") {
+        selection.ksize = Some(ksize.parse()?);
+    }
+    selection.moltype = cmd.value_of("moltype").map(String::from);
+    selection.md5sum = cmd.value_of("md5").map(String::from);
+    if let Some(scaled) = cmd.value_of("scaled") {
+        // 0, the default in search, means no downsampling
+        let scaled = scaled.parse()?;
+        if scaled != 0 {
+            selection.scaled = Some(scaled);
+        }
+    }
+    if let Some(num) = cmd.value_of("num") {
+        selection.num = Some(num.parse()?);
+    }
+    if let Some(seed) = cmd.value_of("seed") {
+        selection.seed = Some(seed.parse()?);
+    }
+    if let Some(name) = cmd.value_of("name") {
+        selection.name = Some(Pattern::substring(name));
+    }
+    if let Some(re) = cmd.value_of("name-regex") {
+        selection.name = Some(Pattern::regex(re)?);
+    }
+    if let Some(spec) = cmd.value_of("picklist") {
+        let picklist = Picklist::from_spec(spec)?;
+        info!("loaded {} distinct values from picklist", picklist.len());
+        selection.picklist = Some(picklist);
+    }
+    Ok(selection)
+}
+
+fn save_sigs(sigs: &[Signature], path: &str) -> Result<(), Error> {
+    Signature::save_path(sigs, path).context(format!("can't save signatures to {}", path))?;
+    Ok(())
+}
+
 fn main() -> Result<(), ExitFailure> {
     //setup_panic!();
 
@@ -163,6 +339,70 @@ fn main() -> Result<(), ExitFailure> {
             assert_eq!(new_sbt.leaves().len(), 100);
             Ok(())
         }
+        Some("compute") => {
+            let cmd = m.subcommand_matches("compute").unwrap();
+
+            let params = sketch_params(cmd)?;
+            let seed = cmd.value_of("seed").unwrap().parse()?;
+            let force = cmd.is_present("force");
+            let singleton = cmd.is_present("singleton");
+
+            let mut builder = SignatureBuilder::new(&params, seed)?;
+            if let Some(name) = cmd.value_of("name") {
+                builder.name(name);
+            }
+
+            let mut all_sigs = Vec::new();
+            for filename in cmd.values_of("filenames").unwrap() {
+                info!("computing signatures for {}", filename);
+                let sigs = if singleton {
+                    builder.singletons_from_path(filename, force)?
+                } else {
+                    let mut file_builder = builder.clone();
+                    let n = file_builder.add_path(filename, force)?;
+                    info!("calculated {} signatures for {} sequences", params.len(), n);
+                    vec![file_builder.build()]
+                };
+
+                if cmd.is_present("output") {
+                    all_sigs.extend(sigs);
+                } else {
+                    let basename = Path::new(filename)
+                        .file_name()
+                        .and_then(|f| f.to_str())
+                        .unwrap_or(filename);
+                    let output = format!("{}.sig", basename);
+                    info!("saving {} signatures to {}", sigs.len(), output);
+                    save_sigs(&sigs, &output)?;
+                }
+            }
+
+            if let Some(output) = cmd.value_of("output") {
+                info!("saving {} signatures to {}", all_sigs.len(), output);
+                save_sigs(&all_sigs, output)?;
+            }
+            Ok(())
+        }
+        Some("extract") => {
+            let cmd = m.subcommand_matches("extract").unwrap();
+            let selection = selection_from_args(cmd)?;
+
+            let mut sigs = Vec::new();
+            for filename in cmd.values_of("signatures").unwrap() {
+                let file = File::open(filename).context(format!("can't open {}", filename))?;
+                let selected = Signature::load_selected(&mut io::BufReader::new(file), &selection)
+                    .context(format!("can't load signatures from {}", filename))?;
+                sigs.extend(selected);
+            }
+            if cmd.is_present("dedup") {
+                sigs = Signature::dedup(sigs);
+            }
+
+            let output = cmd.value_of("output").unwrap();
+            info!("extracted {} signatures to {}", sigs.len(), output);
+            save_sigs(&sigs, output)?;
+            Ok(())
+        }
         Some("search") => {
             let cmd = m.subcommand_matches("search").unwrap();
 
@@ -183,6 +423,7 @@ fn main() -> Result<(), ExitFailure> {
                 } else {
                     None
                 },
+                &selection_from_args(cmd)?,
             )?;
 
             info!(
@@ -210,11 +451,13 @@ fn main() -> Result<(), ExitFailure> {
             let best_only = cmd.is_present("best-only");
             let threshold = cmd.value_of("threshold").unwrap().parse().unwrap();
             let ignore_abundance = cmd.is_present("ignore-abundance");
+            let max_containment = cmd.is_present("max-containment");
             let results = search_databases(
                 query,
                 &databases,
                 threshold,
                 containment,
+                max_containment,
                 best_only,
                 ignore_abundance,
             )?;
@@ -236,11 +479,7 @@ fn main() -> Result<(), ExitFailure> {
             println!("similarity   match");
             println!("----------   -----");
             for sr in &results[..n_matches] {
-                println!(
-                    "{:>6.1}%       {:60}",
-                    sr.similarity * 100.,
-                    sr.match_sig.name.clone().unwrap()
-                );
+                println!("{}", sr.to_row());
             }
 
             if best_only {
@@ -257,13 +496,18 @@ fn main() -> Result<(), ExitFailure> {
                     d = dict(sr._asdict())
                     del d['match_sig']
                     w.writerow(d)
-
-            if args.save_matches:
-                outname = args.save_matches.name
-                info!("saving all matched signatures to \"{}\"", outname)
-                Signature::save_signatures([sr.match_sig for sr in results], args.save_matches)
             */
 
+            if let Some(outname) = cmd.value_of("save-matches") {
+                info!("saving all matched signatures to \"{}\"", outname);
+                let mut wtr = SignatureWriter::create(outname)
+                    .context(format!("can't save matches to {}", outname))?;
+                for sr in &results {
+                    wtr.write(&sr.match_sig)?;
+                }
+                wtr.close()?;
+            }
+
             Ok(())
         }
         _ => {
@@ -272,3 +516,30 @@ fn main() -> Result<(), ExitFailure> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn print_result_with_estimate() {
+        let mut match_sig = Signature::default();
+        match_sig.name = Some("genome".into());
+        let mut sr = Results {
+            similarity: 0.5,
+            match_sig,
+            estimate: None,
+        };
+        assert!(sr.to_row().starts_with("  50.0%       genome"));
+
+        sr.estimate = Some(Estimate {
+            value: 0.5,
+            low: 0.42,
+            high: 0.58,
+            confidence: CONFIDENCE,
+        });
+        assert!(sr
+            .to_row()
+            .starts_with("  50.0%  [42.0-58.0%]       genome"));
+    }
+}
@@ -0,0 +1,347 @@
+//! Selecting sketches from collections of signatures.
+//!
+//! A `Selection` matches sketch parameters (ksize, molecule, scaled, num,
+//! seed), the sketch md5sum, the signature name or filename, and picklists
+//! loaded from CSV files. Signatures with several sketches are split into one
+//! signature per sketch before selecting, same as `load_signatures`.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::str::FromStr;
+
+use failure::{Error, ResultExt};
+use regex::Regex;
+
+use crate::errors::SourmashError;
+use crate::{KmerMinHash, Signature};
+
+/// Text matching for names and filenames.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Substring(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub fn substring(text: &str) -> Pattern {
+        Pattern::Substring(text.into())
+    }
+
+    pub fn regex(re: &str) -> Result<Pattern, Error> {
+        Ok(Pattern::Regex(Regex::new(re)?))
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        match self {
+            Pattern::Substring(s) => text.contains(s.as_str()),
+            Pattern::Regex(re) => re.is_match(text),
+        }
+    }
+}
+
+/// What the values in a picklist are compared with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PicklistColumn {
+    /// Full signature name.
+    Name,
+    /// First word of the signature name (usually an accession).
+    Ident,
+    Md5,
+    /// First 8 characters of the md5sum.
+    Md5Prefix8,
+}
+
+impl FromStr for PicklistColumn {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<PicklistColumn, Error> {
+        match s {
+            "name" => Ok(PicklistColumn::Name),
+            "ident" => Ok(PicklistColumn::Ident),
+            "md5" => Ok(PicklistColumn::Md5),
+            "md5prefix8" => Ok(PicklistColumn::Md5Prefix8),
+            _ => Err(SourmashError::InvalidPicklist {
+                message: format!("unknown column type: {}", s),
+            }
+            .into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Picklist {
+    column_type: PicklistColumn,
+    values: HashSet<String>,
+}
+
+impl Picklist {
+    pub fn new<I>(column_type: PicklistColumn, values: I) -> Picklist
+    where
+        I: IntoIterator<Item = String>,
+    {
+        Picklist {
+            column_type,
+            values: values.into_iter().collect(),
+        }
+    }
+
+    /// Load the values in `column` of a CSV file with a header line.
+    pub fn from_reader<R: io::Read>(
+        rdr: R,
+        column: &str,
+        column_type: PicklistColumn,
+    ) -> Result<Picklist, Error> {
+        let mut rdr = csv::Reader::from_reader(rdr);
+        let pos = rdr
+            .headers()?
+            .iter()
+            .position(|h| h == column)
+            .ok_or_else(|| SourmashError::InvalidPicklist {
+                message: format!("column {} not found", column),
+            })?;
+
+        let mut values = HashSet::new();
+        for row in rdr.records() {
+            if let Some(value) = row?.get(pos) {
+                if !value.is_empty() {
+                    values.insert(value.to_string());
+                }
+            }
+        }
+        Ok(Picklist {
+            column_type,
+            values,
+        })
+    }
+
+    pub fn from_path(
+        path: &str,
+        column: &str,
+        column_type: PicklistColumn,
+    ) -> Result<Picklist, Error> {
+        let file = File::open(path).context(format!("can't open picklist {}", path))?;
+        Picklist::from_reader(io::BufReader::new(file), column, column_type)
+    }
+
+    /// Load a picklist from a `path:column:column_type` description.
+    pub fn from_spec(spec: &str) -> Result<Picklist, Error> {
+        // split from the right, so paths can contain ':'
+        let mut parts = spec.rsplitn(3, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(column_type), Some(column), Some(path)) => {
+                Picklist::from_path(path, column, column_type.parse()?)
+            }
+            _ => Err(SourmashError::InvalidPicklist {
+                message: format!("expected path:column:column_type, got {}", spec),
+            }
+            .into()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn matches(&self, sig: &Signature, mh: &KmerMinHash) -> bool {
+        let name = sig.name.as_deref().unwrap_or("");
+        match self.column_type {
+            PicklistColumn::Name => self.values.contains(name),
+            PicklistColumn::Ident => match name.split_whitespace().next() {
+                Some(ident) => self.values.contains(ident),
+                None => false,
+            },
+            PicklistColumn::Md5 => self.values.contains(&mh.md5sum()),
+            PicklistColumn::Md5Prefix8 => self.values.contains(&mh.md5sum()[..8]),
+        }
+    }
+}
+
+/// Criteria for selecting sketches. Every criterion that is set must match.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    pub ksize: Option<u32>,
+    /// `DNA`, `protein`, `dayhoff` or `hp` (not case sensitive).
+    pub moltype: Option<String>,
+    /// Full md5sum of the sketch, or a prefix of it.
+    pub md5sum: Option<String>,
+    /// Matched against both the signature name and filename.
+    pub name: Option<Pattern>,
+    /// Scaled sketches that can be downsampled to this scaled value.
+    pub scaled: Option<u64>,
+    /// Num sketches with at least this many hashes.
+    pub num: Option<u32>,
+    pub seed: Option<u64>,
+    pub picklist: Option<Picklist>,
+}
+
+impl Selection {
+    pub fn matches(&self, sig: &Signature, mh: &KmerMinHash) -> bool {
+        if let Some(ksize) = self.ksize {
+            if mh.ksize != ksize {
+                return false;
+            }
+        }
+        if let Some(moltype) = &self.moltype {
+            if moltype.to_lowercase() != mh.molecule().to_lowercase() {
+                return false;
+            }
+        }
+        if let Some(scaled) = self.scaled {
+            if !mh.is_scaled() || mh.scaled() > scaled {
+                return false;
+            }
+        }
+        if let Some(num) = self.num {
+            if mh.num == 0 || mh.num < num {
+                return false;
+            }
+        }
+        if let Some(seed) = self.seed {
+            if mh.seed != seed {
+                return false;
+            }
+        }
+        if let Some(name) = &self.name {
+            let in_name = sig.name.as_ref().is_some_and(|n| name.is_match(n));
+            let in_filename = sig.filename.as_ref().is_some_and(|f| name.is_match(f));
+            if !in_name && !in_filename {
+                return false;
+            }
+        }
+        if let Some(md5sum) = &self.md5sum {
+            if !mh.md5sum().starts_with(md5sum.as_str()) {
+                return false;
+            }
+        }
+        if let Some(picklist) = &self.picklist {
+            if !picklist.matches(sig, mh) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Split `sigs` into one signature per sketch, and keep the ones that
+    /// match.
+    pub fn select<I>(&self, sigs: I) -> Vec<Signature>
+    where
+        I: IntoIterator<Item = Signature>,
+    {
+        let mut selected = Vec::new();
+        for mut sig in sigs {
+            let mhs = std::mem::take(&mut sig.signatures);
+            for mh in mhs {
+                if self.matches(&sig, &mh) {
+                    let mut new_sig = sig.clone();
+                    new_sig.signatures = vec![mh];
+                    selected.push(new_sig);
+                }
+            }
+        }
+        selected
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{names, sigs};
+
+    #[test]
+    fn select_params() {
+        let all = Selection::default().select(sigs());
+        assert_eq!(all.len(), 3);
+        assert!(all.iter().all(|s| s.signatures.len() == 1));
+
+        let selection = Selection {
+            moltype: Some("dna".into()),
+            ..Default::default()
+        };
+        assert_eq!(selection.select(sigs()).len(), 2);
+
+        let selection = Selection {
+            ksize: Some(30),
+            num: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(selection.select(sigs()).len(), 1);
+
+        let selection = Selection {
+            scaled: Some(50),
+            ..Default::default()
+        };
+        assert!(selection.select(sigs()).is_empty());
+
+        let selection = Selection {
+            scaled: Some(1000),
+            seed: Some(1),
+            ..Default::default()
+        };
+        let selected = selection.select(sigs());
+        assert_eq!(
+            names(&selected),
+            vec![("GCF_000195995.1 Salmonella enterica".into(), 21)]
+        );
+    }
+
+    #[test]
+    fn select_names_and_md5() {
+        let selection = Selection {
+            name: Some(Pattern::substring("salmonella.fa")),
+            ..Default::default()
+        };
+        assert_eq!(selection.select(sigs()).len(), 1);
+
+        let selection = Selection {
+            name: Some(Pattern::regex(r"^GCF_\d+\.2 ").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(selection.select(sigs()).len(), 2);
+        assert!(Pattern::regex("(unclosed").is_err());
+
+        let md5 = sigs()[0].signatures[1].md5sum();
+        let selection = Selection {
+            md5sum: Some(md5[..8].into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&selection.select(sigs())),
+            vec![("GCF_000005845.2 Escherichia coli".into(), 30)]
+        );
+    }
+
+    #[test]
+    fn picklists() {
+        let csv = "accession,other\nGCF_000195995.1,x\nGCF_000000000.1,y\n";
+        let picklist =
+            Picklist::from_reader(csv.as_bytes(), "accession", PicklistColumn::Ident).unwrap();
+        assert_eq!(picklist.len(), 2);
+
+        let selection = Selection {
+            picklist: Some(picklist),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&selection.select(sigs())),
+            vec![("GCF_000195995.1 Salmonella enterica".into(), 21)]
+        );
+
+        let md5 = sigs()[0].signatures[0].md5sum();
+        let picklist = Picklist::new(PicklistColumn::Md5Prefix8, vec![md5[..8].to_string()]);
+        let selection = Selection {
+            picklist: Some(picklist),
+            ..Default::default()
+        };
+        assert_eq!(selection.select(sigs()).len(), 1);
+
+        let err = Picklist::from_reader(csv.as_bytes(), "md5", PicklistColumn::Md5).unwrap_err();
+        assert_error_code!(err, InvalidPicklist);
+        assert!("accession".parse::<PicklistColumn>().is_err());
+        assert!(Picklist::from_spec("no-columns.csv").is_err());
+    }
+}
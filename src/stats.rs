@@ -0,0 +1,181 @@
+//! Confidence intervals for similarity estimates from sketches.
+//!
+//! The fraction of shared hashes in a sketch (Jaccard or containment) is
+//! treated as a binomial proportion, with a Wilson score interval. For scaled
+//! sketches the hashes are a sample of roughly `size * scaled` k-mers, so a
+//! finite population correction shrinks the interval as `scaled` gets
+//! smaller (with `scaled = 1` the sketch has every k-mer, and the estimate is
+//! exact).
+
+use crate::containment_to_ani;
+
+/// A point estimate together with its confidence interval.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub value: f64,
+    pub low: f64,
+    pub high: f64,
+    /// Confidence level of the interval, e.g. `0.95`.
+    pub confidence: f64,
+}
+
+impl Estimate {
+    /// Convert a containment estimate into an ANI estimate for k-mers of
+    /// length `ksize`. The conversion is monotonic, so the interval bounds
+    /// are converted directly.
+    pub fn to_ani(&self, ksize: u32) -> Estimate {
+        Estimate {
+            value: containment_to_ani(self.value, ksize),
+            low: containment_to_ani(self.low, ksize),
+            high: containment_to_ani(self.high, ksize),
+            confidence: self.confidence,
+        }
+    }
+}
+
+/// Estimate a proportion from `common` hashes out of `size` hashes sampled
+/// from a sketch with the given `scaled` factor (`0` for num sketches).
+pub fn proportion_estimate(common: u64, size: u64, scaled: u64, confidence: f64) -> Estimate {
+    if size == 0 {
+        return Estimate {
+            value: 0.0,
+            low: 0.0,
+            high: 1.0,
+            confidence,
+        };
+    }
+
+    let n = size as f64;
+    let p = common as f64 / n;
+    let z = normal_quantile(1.0 - (1.0 - confidence) / 2.0);
+    let z2 = z * z;
+
+    let denom = 1.0 + z2 / n;
+    let center = (p + z2 / (2.0 * n)) / denom;
+    let half_width = z * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / denom
+        * finite_population_correction(size, scaled);
+
+    Estimate {
+        value: p,
+        low: f64::max(0.0, f64::min(p, center - half_width)),
+        high: f64::min(1.0, f64::max(p, center + half_width)),
+        confidence,
+    }
+}
+
+/// `sqrt((N - n) / (N - 1))`, where the population size `N` is estimated as
+/// `n * scaled`. Num sketches (`scaled = 0`) don't have a population size, so
+/// no correction is applied.
+fn finite_population_correction(size: u64, scaled: u64) -> f64 {
+    if scaled == 0 {
+        return 1.0;
+    }
+    let n = size as f64;
+    let population = n * scaled as f64;
+    if population <= 1.0 {
+        return 0.0;
+    }
+    ((population - n) / (population - 1.0)).sqrt()
+}
+
+/// Inverse of the standard normal CDF, using Acklam's rational approximation
+/// (relative error below 1.2e-9).
+pub fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    const P_LOW: f64 = 0.02425;
+
+    if p <= 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if p >= 1.0 {
+        return f64::INFINITY;
+    }
+
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+
+    if p < P_LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - P_LOW {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quantiles() {
+        assert_eq!(normal_quantile(0.5), 0.0);
+        assert!((normal_quantile(0.975) - 1.959_963_984_540_054).abs() < 1e-8);
+        assert!((normal_quantile(0.025) + 1.959_963_984_540_054).abs() < 1e-8);
+        assert!((normal_quantile(0.995) - 2.575_829_303_548_901).abs() < 1e-8);
+        assert!((normal_quantile(1e-5) + 4.264_890_793_922_602).abs() < 1e-7);
+    }
+
+    #[test]
+    fn wilson_interval() {
+        let est = proportion_estimate(50, 100, 0, 0.95);
+        assert_eq!(est.value, 0.5);
+        assert!((est.low - 0.403_831).abs() < 1e-5);
+        assert!((est.high - 0.596_169).abs() < 1e-5);
+
+        // no hashes in common still has some uncertainty
+        let est = proportion_estimate(0, 100, 0, 0.95);
+        assert_eq!(est.low, 0.0);
+        assert!(est.high > 0.0);
+
+        let est = proportion_estimate(0, 0, 0, 0.95);
+        assert_eq!((est.low, est.high), (0.0, 1.0));
+    }
+
+    #[test]
+    fn finite_population() {
+        let num = proportion_estimate(50, 100, 0, 0.95);
+        let coarse = proportion_estimate(50, 100, 1000, 0.95);
+        let fine = proportion_estimate(50, 100, 2, 0.95);
+
+        assert!(coarse.high - coarse.low <= num.high - num.low);
+        assert!(fine.high - fine.low < coarse.high - coarse.low);
+
+        // every k-mer is in the sketch
+        let exact = proportion_estimate(50, 100, 1, 0.95);
+        assert_eq!((exact.low, exact.value, exact.high), (0.5, 0.5, 0.5));
+    }
+}
@@ -0,0 +1,421 @@
+//! Reading and writing signatures one at a time.
+//!
+//! `Signature::from_reader` builds the whole collection in memory before any
+//! filtering happens. `SignatureIter` only keeps the signature being parsed:
+//! the input is scanned for the end of each top-level JSON object, and only
+//! those bytes are deserialized. It accepts a JSON array of signatures, any
+//! number of concatenated arrays or objects (including one signature per
+//! line), the binary format and compressed versions of all of them.
+//!
+//! `SignatureWriter` goes the other way, appending signatures to a JSON
+//! array as they are produced.
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use failure::Error;
+use serde::de::Error as DeError;
+
+use crate::binary;
+use crate::file::{compression_from_extension, get_decoder, get_output, Output};
+use crate::selection::Selection;
+use crate::{RawSignature, Signature};
+
+enum Format {
+    Json {
+        /// Inside a top-level array.
+        in_array: bool,
+        /// An array element was just read, so a `,` or `]` comes next.
+        after_element: bool,
+    },
+    Binary {
+        remaining: usize,
+    },
+}
+
+pub struct SignatureIter<'a> {
+    rdr: BufReader<Box<dyn Read + 'a>>,
+    format: Format,
+    selection: Option<Selection>,
+    check_md5sum: bool,
+    /// Signatures split from the last one read that weren't returned yet.
+    pending: VecDeque<Signature>,
+    /// Bytes of the JSON object being read.
+    buf: Vec<u8>,
+    done: bool,
+}
+
+fn syntax_error(message: &str) -> Error {
+    serde_json::Error::custom(message).into()
+}
+
+impl<'a> SignatureIter<'a> {
+    pub fn new<R: Read + 'a>(rdr: R) -> Result<SignatureIter<'a>, Error> {
+        let (rdr, _) = get_decoder(rdr)?;
+        let (is_binary, rdr) = binary::detect(rdr)?;
+        let mut rdr = BufReader::new(Box::new(rdr) as Box<dyn Read + 'a>);
+
+        let format = if is_binary {
+            Format::Binary {
+                remaining: binary::read_header(&mut rdr)?,
+            }
+        } else {
+            Format::Json {
+                in_array: false,
+                after_element: false,
+            }
+        };
+
+        Ok(SignatureIter {
+            rdr,
+            format,
+            selection: None,
+            check_md5sum: false,
+            pending: VecDeque::new(),
+            buf: Vec::new(),
+            done: false,
+        })
+    }
+
+    /// Only return sketches matching `selection`, split into one signature
+    /// per sketch (see `Selection::select`).
+    pub fn with_selection(mut self, selection: Selection) -> SignatureIter<'a> {
+        self.selection = Some(selection);
+        self
+    }
+
+    /// Fail if the `md5sum` stored for a sketch doesn't match its content,
+    /// as in `Signature::from_reader_checked`.
+    pub fn check_md5sum(mut self) -> SignatureIter<'a> {
+        self.check_md5sum = true;
+        self
+    }
+
+    fn peek(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.rdr.fill_buf()?.first().cloned())
+    }
+
+    /// Copy the JSON object starting at the current position into `buf`.
+    fn read_object(&mut self) -> Result<(), Error> {
+        self.buf.clear();
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+
+        loop {
+            let available = self.rdr.fill_buf()?;
+            if available.is_empty() {
+                return Err(syntax_error("EOF while parsing a signature"));
+            }
+
+            let mut end = None;
+            for (i, &c) in available.iter().enumerate() {
+                if in_string {
+                    if escaped {
+                        escaped = false;
+                    } else if c == b'\\' {
+                        escaped = true;
+                    } else if c == b'"' {
+                        in_string = false;
+                    }
+                    continue;
+                }
+
+                match c {
+                    b'"' => in_string = true,
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            end = Some(i + 1);
+                            break;
+                        }
+                    }
+                    _ => (),
+                }
+            }
+
+            let used = end.unwrap_or(available.len());
+            self.buf.extend_from_slice(&available[..used]);
+            self.rdr.consume(used);
+            if end.is_some() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// The next signature in the input, before selection.
+    fn next_signature(&mut self) -> Result<Option<Signature>, Error> {
+        let (mut in_array, mut after_element) = match &mut self.format {
+            Format::Binary { remaining } => {
+                if *remaining == 0 {
+                    return Ok(None);
+                }
+                *remaining -= 1;
+                return binary::read_signature(&mut self.rdr).map(Some);
+            }
+            Format::Json {
+                in_array,
+                after_element,
+            } => (*in_array, *after_element),
+        };
+
+        let result = loop {
+            match self.peek()? {
+                None if in_array => break Err(syntax_error("EOF while parsing a list")),
+                None => break Ok(None),
+                Some(c) if c.is_ascii_whitespace() => self.rdr.consume(1),
+                Some(b'[') if !in_array => {
+                    in_array = true;
+                    after_element = false;
+                    self.rdr.consume(1);
+                }
+                Some(b']') if in_array => {
+                    in_array = false;
+                    self.rdr.consume(1);
+                }
+                Some(b',') if in_array && after_element => {
+                    after_element = false;
+                    self.rdr.consume(1);
+                }
+                Some(b'{') if !after_element => {
+                    self.read_object()?;
+                    after_element = in_array;
+                    let raw: RawSignature = serde_json::from_slice(&self.buf)?;
+                    break if self.check_md5sum {
+                        Signature::try_from_checked(raw).map(Some)
+                    } else {
+                        Signature::try_from(raw).map(Some)
+                    };
+                }
+                Some(c) => {
+                    break Err(syntax_error(&format!(
+                        "unexpected character '{}'",
+                        char::from(c)
+                    )))
+                }
+            }
+        };
+
+        self.format = Format::Json {
+            in_array,
+            after_element,
+        };
+        result
+    }
+}
+
+impl<'a> Iterator for SignatureIter<'a> {
+    type Item = Result<Signature, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            if let Some(sig) = self.pending.pop_front() {
+                return Some(Ok(sig));
+            }
+
+            match self.next_signature() {
+                Ok(Some(sig)) => match &self.selection {
+                    Some(selection) => self.pending.extend(selection.select(vec![sig])),
+                    None => return Some(Ok(sig)),
+                },
+                Ok(None) => self.done = true,
+                Err(e) => {
+                    // there is no way to resync after a parsing error
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+/// Writes signatures to a JSON array, one at a time. The array is only
+/// closed by `finish`, so the output is not valid JSON until it is called.
+pub struct SignatureWriter<W: Write> {
+    wtr: W,
+    count: usize,
+}
+
+impl<W: Write> SignatureWriter<W> {
+    /// Start a JSON array in `wtr`.
+    pub fn new(mut wtr: W) -> Result<SignatureWriter<W>, Error> {
+        wtr.write_all(b"[")?;
+        Ok(SignatureWriter { wtr, count: 0 })
+    }
+
+    pub fn write(&mut self, sig: &Signature) -> Result<(), Error> {
+        if self.count > 0 {
+            self.wtr.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut self.wtr, sig)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Number of signatures written so far.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Close the array and flush, returning the underlying writer. Writers
+    /// opened with `create` are closed with `close` instead.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.wtr.write_all(b"]")?;
+        self.wtr.flush()?;
+        Ok(self.wtr)
+    }
+}
+
+impl SignatureWriter<Output> {
+    /// Write to `path` (`-` for stdout), compressed according to the
+    /// extension as in `Signature::save_path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<SignatureWriter<Output>, Error> {
+        let compression = compression_from_extension(&path);
+        SignatureWriter::new(get_output(path, compression)?)
+    }
+
+    /// Close the array and finish the compressed stream.
+    pub fn close(self) -> Result<(), Error> {
+        self.finish()?.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{names, sig};
+
+    #[test]
+    fn json_layouts() {
+        let a = serde_json::to_string(&sig("a", &[21, 31])).unwrap();
+        let b = serde_json::to_string(&sig("b, {\"tricky\"} ]", &[31])).unwrap();
+        let expected = vec![("a".to_string(), 21), ("b, {\"tricky\"} ]".to_string(), 31)];
+
+        for input in &[
+            format!("[{},{}]", a, b),
+            format!("  [\n  {} ,\n  {}\n]\n", a, b),
+            format!("{}\n{}\n", a, b),
+            format!("[{}][{}]", a, b),
+            format!("{}[{}]", a, b),
+        ] {
+            let loaded: Vec<Signature> = SignatureIter::new(input.as_bytes())
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert_eq!(names(&loaded), expected);
+        }
+
+        for input in &["", "[]", " \n", "[][]"] {
+            assert!(SignatureIter::new(input.as_bytes())
+                .unwrap()
+                .next()
+                .is_none());
+        }
+
+        for input in &[
+            format!("[{}", a),
+            format!("[{} {}]", a, b),
+            format!("[,{}]", a),
+            format!("{},{}", a, b),
+            a[..a.len() - 1].to_string(),
+        ] {
+            let results: Vec<_> = SignatureIter::new(input.as_bytes()).unwrap().collect();
+            assert!(results.last().unwrap().is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn selection_and_binary() {
+        let sigs = vec![sig("a", &[21, 31]), sig("b", &[31]), sig("c", &[21])];
+        let json = serde_json::to_vec(&sigs).unwrap();
+        let mut bin = Vec::new();
+        binary::write_signatures(&mut bin, &sigs).unwrap();
+
+        for input in &[&json, &bin] {
+            let selection = Selection {
+                ksize: Some(31),
+                ..Default::default()
+            };
+            let iter = SignatureIter::new(&input[..])
+                .unwrap()
+                .with_selection(selection);
+            let selected: Vec<_> = iter.collect::<Result<_, _>>().unwrap();
+            assert_eq!(names(&selected), vec![("a".into(), 31), ("b".into(), 31)]);
+
+            let all: Vec<Signature> = SignatureIter::new(&input[..])
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert_eq!(all.len(), 3);
+            assert_eq!(all[0].signatures.len(), 2);
+        }
+
+        // validation errors keep their codes
+        let corrupted = String::from_utf8(json)
+            .unwrap()
+            .replace("[31,100]", "[100,31]");
+        let err = SignatureIter::new(corrupted.as_bytes())
+            .unwrap()
+            .find_map(|s| s.err())
+            .unwrap();
+        assert_error_code!(err, UnsortedHashes);
+
+        // changing the hashes without updating the md5sum
+        let tampered = String::from_utf8(serde_json::to_vec(&sigs).unwrap())
+            .unwrap()
+            .replace("[21,100]", "[22,100]");
+        let unchecked: Vec<_> = SignatureIter::new(tampered.as_bytes()).unwrap().collect();
+        assert!(unchecked.iter().all(Result::is_ok));
+        let err = SignatureIter::new(tampered.as_bytes())
+            .unwrap()
+            .check_md5sum()
+            .find_map(|s| s.err())
+            .unwrap();
+        assert_error_code!(err, InvalidMd5sum);
+    }
+
+    #[test]
+    fn write_incrementally() {
+        let sigs = vec![sig("a", &[21, 31]), sig("b", &[31])];
+
+        let wtr = SignatureWriter::new(Vec::new()).unwrap();
+        assert_eq!(wtr.finish().unwrap(), b"[]");
+
+        let mut wtr = SignatureWriter::new(Vec::new()).unwrap();
+        for sig in &sigs {
+            wtr.write(sig).unwrap();
+        }
+        assert_eq!(wtr.len(), 2);
+        let out = wtr.finish().unwrap();
+
+        let mut saved = Vec::new();
+        Signature::save_signatures(&mut saved, &sigs).unwrap();
+        assert_eq!(out, saved);
+
+        let loaded = Signature::from_reader(&mut &out[..]).unwrap();
+        assert_eq!(loaded, sigs);
+        assert_eq!(loaded[0].signatures, sigs[0].signatures);
+
+        let mut path = std::env::temp_dir();
+        path.push(format!("sourmash-stream-{}.sig.gz", std::process::id()));
+        {
+            let mut wtr = SignatureWriter::create(&path).unwrap();
+            for sig in &sigs {
+                wtr.write(sig).unwrap();
+            }
+            wtr.close().unwrap();
+        }
+        let loaded = Signature::from_path(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, sigs);
+    }
+}
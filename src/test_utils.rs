@@ -0,0 +1,79 @@
+//! Fixtures shared by the unit tests.
+
+use crate::{max_hash_for_scaled, HashFunction, KmerMinHash, Signature};
+
+/// A signature named `name` with one small DNA sketch per k-mer size.
+pub fn sig(name: &str, ksizes: &[u32]) -> Signature {
+    let mut sig = Signature::default();
+    sig.name = Some(name.into());
+    sig.signatures = ksizes
+        .iter()
+        .map(|&k| {
+            let mut mh = KmerMinHash::new(10, k, false, 42, 0, false);
+            mh.add_many(&[u64::from(k), 100]).unwrap();
+            mh
+        })
+        .collect();
+    sig
+}
+
+/// Two signatures with DNA and protein sketches, different names,
+/// filenames and seeds, for selection tests.
+pub fn sigs() -> Vec<Signature> {
+    let mut dna = KmerMinHash::new(0, 21, false, 42, max_hash_for_scaled(100), false);
+    dna.add_many(&[1, 2, 3]).unwrap();
+    let mut prot = KmerMinHash::new(500, 30, true, 42, 0, false);
+    prot.add_many(&[4, 5]).unwrap();
+
+    let mut a = Signature::default();
+    a.name = Some("GCF_000005845.2 Escherichia coli".into());
+    a.filename = Some("ecoli.fa".into());
+    a.signatures = vec![dna.clone(), prot];
+
+    let mut b = Signature::default();
+    b.name = Some("GCF_000195995.1 Salmonella enterica".into());
+    b.filename = Some("salmonella.fa.gz".into());
+    dna.add_hash(10);
+    dna.seed = 1;
+    b.signatures = vec![dna];
+
+    vec![a, b]
+}
+
+/// Signatures using the less common options (cardinality tracking, reduced
+/// alphabets, translation tables, other hash functions), for roundtrip tests.
+pub fn varied_sigs() -> Vec<Signature> {
+    let mut scaled = KmerMinHash::new(0, 21, false, 42, max_hash_for_scaled(10), true);
+    scaled.track_cardinality(4).unwrap();
+    scaled
+        .add_sequence(b"ACGTTGCAACGGCATCGATCGACTAGCTACGATCGACTGACTAGCTAAC", false)
+        .unwrap();
+    scaled
+        .add_sequence(b"ACGTTGCAACGGCATCGATCG", false)
+        .unwrap();
+
+    let mut prot = KmerMinHash::new(10, 30, true, 42, 0, false);
+    prot.dayhoff = true;
+    prot.translation_table = 11;
+    prot.add_many(&[1, u64::MAX, 127, 128]).unwrap();
+
+    let mut a = Signature::default();
+    a.name = Some("first".into());
+    a.signatures = vec![scaled, prot];
+
+    let mut b = Signature::default();
+    b.filename = Some("ünïcode.fa".into());
+    b.hash_function = "0.xxhash64".into();
+    let mut mh = KmerMinHash::new(5, 31, false, 42, 0, false);
+    mh.hash_function = HashFunction::XxHash64;
+    b.signatures = vec![mh];
+
+    vec![a, b]
+}
+
+/// Name and ksize of the first sketch of each signature.
+pub fn names(sigs: &[Signature]) -> Vec<(String, u32)> {
+    sigs.iter()
+        .map(|s| (s.name.clone().unwrap(), s.signatures[0].ksize))
+        .collect()
+}
@@ -0,0 +1,266 @@
+//! Keeping track of the k-mers behind the hashes in a sketch.
+//!
+//! `KmerMinHash` only stores hashes. `KmerTracker` wraps a DNA sketch and,
+//! while adding sequences, also records the canonical k-mer and every
+//! position where each retained hash occurred, so matches found in a search
+//! can be mapped back to genomic coordinates.
+
+use std::collections::HashMap;
+use std::io;
+
+use failure::Error;
+use serde_derive::Serialize;
+
+use crate::errors::SourmashError;
+use crate::fastx::{add_records_with, Record};
+use crate::{for_each_canonical_kmer, Intersection, KmerMinHash};
+
+/// Where a k-mer occurred: index of the record (see `KmerTracker::records`)
+/// and 0-based offset of the k-mer start in the record sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct KmerPosition {
+    pub record: usize,
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrackedKmer {
+    pub hash: u64,
+    /// Canonical form of the k-mer (the smallest of the k-mer and its
+    /// reverse complement).
+    pub kmer: String,
+    pub positions: Vec<KmerPosition>,
+}
+
+pub struct KmerTracker {
+    mh: KmerMinHash,
+    records: Vec<String>,
+    kmers: HashMap<u64, TrackedKmer>,
+}
+
+impl KmerTracker {
+    /// Start tracking k-mers for `mh`. Only DNA sketches are supported, and
+    /// hashes already in `mh` are only tracked once they show up again.
+    pub fn new(mh: KmerMinHash) -> Result<KmerTracker, Error> {
+        if mh.is_protein {
+            return Err(SourmashError::MismatchDNAProt.into());
+        }
+
+        Ok(KmerTracker {
+            mh,
+            records: Vec::new(),
+            kmers: HashMap::new(),
+        })
+    }
+
+    /// Add a DNA sequence, recording positions under the record `name`.
+    /// `force` works the same as in `KmerMinHash::add_sequence`.
+    pub fn add_sequence(&mut self, name: &str, seq: &[u8], force: bool) -> Result<(), Error> {
+        let record = self.records.len();
+        self.records.push(name.into());
+
+        let ksize = self.mh.ksize as usize;
+        let (mh, kmers) = (&mut self.mh, &mut self.kmers);
+        let result = for_each_canonical_kmer(seq, ksize, force, |offset, kmer| {
+            let hash = mh.hash_function.hash(kmer, mh.seed);
+            mh.add_hash(hash);
+
+            // a hash that is not kept now can't be kept later, since sketches
+            // only get more selective as hashes are added.
+            if mh.mins.binary_search(&hash).is_ok() {
+                kmers
+                    .entry(hash)
+                    .or_insert_with(|| TrackedKmer {
+                        hash,
+                        kmer: String::from_utf8_lossy(kmer).into_owned(),
+                        positions: Vec::new(),
+                    })
+                    .positions
+                    .push(KmerPosition { record, offset });
+            }
+        });
+
+        self.prune();
+        result
+    }
+
+    /// Add every record, returning how many were added. See
+    /// `fastx::add_records`.
+    pub fn add_records<I>(&mut self, records: I, force: bool) -> Result<usize, Error>
+    where
+        I: IntoIterator<Item = Result<Record, Error>>,
+    {
+        add_records_with(records, force, |record| {
+            self.add_sequence(&record.name, &record.seq, force)
+        })
+    }
+
+    /// Forget hashes that were removed from num sketches by smaller ones.
+    fn prune(&mut self) {
+        if self.kmers.len() > self.mh.mins.len() {
+            let mins = &self.mh.mins;
+            self.kmers
+                .retain(|hash, _| mins.binary_search(hash).is_ok());
+        }
+    }
+
+    pub fn minhash(&self) -> &KmerMinHash {
+        &self.mh
+    }
+
+    pub fn into_minhash(self) -> KmerMinHash {
+        self.mh
+    }
+
+    /// Names of the records added, in order. `KmerPosition::record` is an
+    /// index into this.
+    pub fn records(&self) -> &[String] {
+        &self.records
+    }
+
+    pub fn get(&self, hash: u64) -> Option<&TrackedKmer> {
+        self.kmers.get(&hash)
+    }
+
+    /// All tracked k-mers, sorted by hash.
+    pub fn kmers(&self) -> Vec<&TrackedKmer> {
+        self.mh
+            .mins
+            .iter()
+            .filter_map(|hash| self.kmers.get(hash))
+            .collect()
+    }
+
+    /// Tracked k-mers for the hashes shared with `other` (for example a
+    /// match from a search), sorted by hash.
+    pub fn matches(&self, other: &KmerMinHash) -> Result<Vec<&TrackedKmer>, Error> {
+        self.mh.check_compatible(other)?;
+        Ok(Intersection::new(self.mh.mins.iter(), other.mins.iter())
+            .filter_map(|hash| self.kmers.get(hash))
+            .collect())
+    }
+
+    /// Write one CSV row (`hash,kmer,record,offset`) per occurrence of the
+    /// given k-mers, with record names instead of indices.
+    pub fn write_csv<W: io::Write>(&self, kmers: &[&TrackedKmer], writer: W) -> Result<(), Error> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        wtr.write_record(["hash", "kmer", "record", "offset"])?;
+        for tracked in kmers {
+            for pos in &tracked.positions {
+                wtr.write_record(&[
+                    tracked.hash.to_string(),
+                    tracked.kmer.clone(),
+                    self.records[pos.record].clone(),
+                    pos.offset.to_string(),
+                ])?;
+            }
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fastx::FastxReader;
+    use crate::max_hash_for_scaled;
+
+    fn canonical(kmer: &[u8]) -> String {
+        let rc: Vec<u8> = kmer
+            .iter()
+            .rev()
+            .map(|b| match b {
+                b'A' => b'T',
+                b'C' => b'G',
+                b'G' => b'C',
+                _ => b'A',
+            })
+            .collect();
+        String::from_utf8(std::cmp::min(kmer.to_vec(), rc)).unwrap()
+    }
+
+    #[test]
+    fn tracks_retained_hashes() {
+        let data = b">r1\nACGTTGCAACGGCATCGATCGACTAG\n>r2 second\nGGCATCGATCGAGTTTACAGATTTA\n";
+        let seqs = [
+            &b"ACGTTGCAACGGCATCGATCGACTAG"[..],
+            &b"GGCATCGATCGAGTTTACAGATTTA"[..],
+        ];
+
+        for mh in vec![
+            KmerMinHash::new(5, 7, false, 42, 0, false),
+            KmerMinHash::new(0, 7, false, 42, max_hash_for_scaled(2), true),
+        ] {
+            let mut tracker = KmerTracker::new(mh.clone()).unwrap();
+            assert_eq!(
+                tracker
+                    .add_records(FastxReader::new(&data[..]), false)
+                    .unwrap(),
+                2
+            );
+            assert_eq!(
+                tracker.records(),
+                &["r1".to_string(), "r2 second".to_string()]
+            );
+
+            // the sketch is the same as without tracking
+            let mut expected = mh.clone();
+            for seq in &seqs {
+                expected.add_sequence(seq, false).unwrap();
+            }
+            assert_eq!(tracker.minhash(), &expected);
+
+            let kmers = tracker.kmers();
+            assert_eq!(kmers.len(), expected.size());
+            assert_eq!(tracker.kmers.len(), expected.size());
+            for tracked in kmers {
+                assert!(!tracked.positions.is_empty());
+                for pos in &tracked.positions {
+                    let seq = seqs[pos.record];
+                    assert_eq!(canonical(&seq[pos.offset..pos.offset + 7]), tracked.kmer);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn export_matches() {
+        let mut tracker = KmerTracker::new(KmerMinHash::new(
+            0,
+            5,
+            false,
+            42,
+            max_hash_for_scaled(1),
+            false,
+        ))
+        .unwrap();
+        tracker.add_sequence("chr1", b"ACGTACGTTT", false).unwrap();
+
+        let mut query = tracker.minhash().copy_empty();
+        query.add_sequence(b"CGTTT", false).unwrap();
+
+        let matches = tracker.matches(&query).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].kmer, "AAACG");
+        assert_eq!(
+            matches[0].positions,
+            vec![KmerPosition {
+                record: 0,
+                offset: 5
+            }]
+        );
+
+        let mut out = Vec::new();
+        tracker.write_csv(&matches, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "hash,kmer,record,offset\n{},AAACG,chr1,5\n",
+                matches[0].hash
+            )
+        );
+
+        assert!(KmerTracker::new(KmerMinHash::new(0, 15, true, 42, 0, false)).is_err());
+    }
+}
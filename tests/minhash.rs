@@ -1,6 +1,13 @@
+use std::convert::TryFrom;
 
+use proptest::{prop_assert, prop_assert_eq, proptest, proptest_helper};
 
-use sourmash::KmerMinHash;
+use sourmash::assert_error_code;
+use sourmash::errors::SourmashError;
+use sourmash::{
+    containment_to_ani, jaccard_to_ani, max_hash_for_scaled, scaled_for_max_hash, Difference,
+    HashFunction, Intersection, KmerMinHash, Union,
+};
 
 #[test]
 fn throws_error() {
@@ -81,3 +88,616 @@ fn compare() {
     assert!(a.compare(&b).unwrap() >= 0.3);
     assert!(b.compare(&a).unwrap() >= 0.3);
 }
+
+#[test]
+fn scaled_roundtrip() {
+    let mh = KmerMinHash::new_scaled(1000, 21, false, 42, false);
+    assert_eq!(mh.num, 0);
+    assert_eq!(mh.max_hash, max_hash_for_scaled(1000));
+    assert_eq!(mh.scaled(), 1000);
+    assert_eq!(scaled_for_max_hash(max_hash_for_scaled(1)), 1);
+    assert_eq!(max_hash_for_scaled(0), 0);
+}
+
+#[test]
+fn scaled_is_not_capped() {
+    let mut mh = KmerMinHash::new_scaled(1, 21, false, 42, true);
+    for hash in 1..=5000 {
+        mh.add_hash(hash);
+    }
+    mh.add_hash(10);
+    assert_eq!(mh.size(), 5000);
+    assert_eq!(mh.abunds.as_ref().unwrap()[9], 2);
+
+    let mut small = KmerMinHash::new_scaled(100, 21, false, 42, false);
+    small.add_hash(max_hash_for_scaled(100) + 1);
+    small.add_hash(max_hash_for_scaled(100));
+    assert_eq!(small.mins, vec![max_hash_for_scaled(100)]);
+}
+
+#[test]
+fn merge_scaled_keeps_abundances_aligned() {
+    let mut a = KmerMinHash::new_scaled(1, 21, false, 42, true);
+    let mut b = KmerMinHash::new_scaled(1, 21, false, 42, true);
+    a.add_many(&[1, 3, 5, 5]).unwrap();
+    b.add_many(&[2, 3, 6]).unwrap();
+
+    a.merge(&b).unwrap();
+    assert_eq!(a.mins, vec![1, 2, 3, 5, 6]);
+    assert_eq!(a.abunds, Some(vec![1, 1, 2, 2, 1]));
+
+    let mut c = KmerMinHash::new(3, 21, false, 42, 0, false);
+    c.add_many(&[10, 20, 30]).unwrap();
+    let mut d = KmerMinHash::new(3, 21, false, 42, 0, false);
+    d.add_many(&[5, 15, 25]).unwrap();
+    c.merge(&d).unwrap();
+    assert_eq!(c.mins, vec![5, 10, 15]);
+    assert_eq!(c.abunds, None);
+}
+
+#[test]
+fn serialize_scaled() {
+    let mut mh = KmerMinHash::new_scaled(2000, 31, false, 42, false);
+    mh.add_many(&[1, 2, 3]).unwrap();
+
+    let json = serde_json::to_string(&mh).unwrap();
+    assert!(json.contains("\"scaled\":2000"));
+
+    let loaded: KmerMinHash = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded, mh);
+    assert_eq!(loaded.scaled(), 2000);
+}
+
+#[test]
+fn downsample() {
+    let mut mh = KmerMinHash::new_scaled(1, 21, false, 42, true);
+    mh.add_many(&[10, 20, 20, 30, 40, 40, 40]).unwrap();
+
+    let by_num = mh.downsample_num(2).unwrap();
+    assert_eq!(by_num.num, 2);
+    assert_eq!(by_num.max_hash, 0);
+    assert_eq!(by_num.mins, vec![10, 20]);
+    assert_eq!(by_num.abunds, Some(vec![1, 2]));
+
+    let by_max_hash = mh.downsample_max_hash(35).unwrap();
+    assert_eq!(by_max_hash.max_hash, 35);
+    assert_eq!(by_max_hash.mins, vec![10, 20, 30]);
+    assert_eq!(by_max_hash.abunds, Some(vec![1, 2, 1]));
+
+    let mut big = KmerMinHash::new_scaled(10, 21, false, 42, false);
+    big.add_many(&[1, 2, 3]).unwrap();
+    let smaller = big.downsample_scaled(100).unwrap();
+    assert_eq!(smaller.scaled(), 100);
+    assert!(big.downsample_scaled(5).is_err());
+}
+
+#[test]
+fn downsample_num_sketch() {
+    let mut mh = KmerMinHash::new(3, 21, false, 42, 0, false);
+    mh.add_many(&[10, 20, 30, 40]).unwrap();
+
+    assert!(mh.downsample_num(5).is_err());
+    assert_eq!(mh.downsample_num(1).unwrap().mins, vec![10]);
+
+    // all hashes below 25 are known, but not all below 35
+    assert_eq!(mh.downsample_max_hash(25).unwrap().mins, vec![10, 20]);
+    assert!(mh.downsample_max_hash(35).is_err());
+}
+
+#[test]
+fn compare_with_abundance() {
+    let mut a = KmerMinHash::new_scaled(1, 21, false, 42, true);
+    let mut b = KmerMinHash::new_scaled(1, 21, false, 42, true);
+    a.add_many_with_abund(&[(1, 1), (2, 10)]).unwrap();
+    b.add_many_with_abund(&[(1, 1), (2, 1)]).unwrap();
+
+    assert_eq!(a.jaccard(&b).unwrap(), 1.0);
+    assert_eq!(a.similarity(&b, true).unwrap(), 1.0);
+
+    let angular = a.compare(&b).unwrap();
+    assert!(angular < 0.6 && angular > 0.5);
+    assert_eq!(angular, a.angular_similarity(&b).unwrap());
+    assert_eq!(angular, b.angular_similarity(&a).unwrap());
+
+    // scaling all abundances doesn't change the angle
+    let mut c = KmerMinHash::new_scaled(1, 21, false, 42, true);
+    c.add_many_with_abund(&[(1, 3), (2, 30)]).unwrap();
+    assert!((a.angular_similarity(&c).unwrap() - 1.0).abs() < 1e-6);
+
+    let flat = KmerMinHash::new_scaled(1, 21, false, 42, false);
+    assert!(a.angular_similarity(&flat).is_err());
+    assert_eq!(a.compare(&flat).unwrap(), 0.0);
+}
+
+// The window-by-window implementation add_sequence used to have, to check the
+// rolling version produces the same hashes.
+fn naive_add_sequence(mh: &mut KmerMinHash, seq: &[u8]) {
+    let sequence: Vec<u8> = seq.iter().map(u8::to_ascii_uppercase).collect();
+    if sequence.len() < mh.ksize as usize {
+        return;
+    }
+    for kmer in sequence.windows(mh.ksize as usize) {
+        if kmer.iter().all(|b| b"ACGT".contains(b)) {
+            let rc: Vec<u8> = kmer
+                .iter()
+                .rev()
+                .map(|b| match b {
+                    b'A' => b'T',
+                    b'C' => b'G',
+                    b'G' => b'C',
+                    _ => b'A',
+                })
+                .collect();
+            if kmer < &rc[..] {
+                mh.add_word(kmer);
+            } else {
+                mh.add_word(&rc);
+            }
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn rolling_hashes_match_naive(seq in "[ACGTNacgtn]{0,3000}", ksize in 1u32..40) {
+        let mut rolling = KmerMinHash::new(0, ksize, false, 42, 0, true);
+        let mut naive = KmerMinHash::new(0, ksize, false, 42, 0, true);
+
+        rolling.add_sequence(seq.as_bytes(), true).unwrap();
+        naive_add_sequence(&mut naive, seq.as_bytes());
+
+        prop_assert_eq!(rolling, naive);
+    }
+}
+
+#[test]
+fn invalid_dna_reports_first_kmer() {
+    let mut mh = KmerMinHash::new(0, 3, false, 42, 0, false);
+    let err = mh.add_sequence(b"acgtNacg", false).unwrap_err();
+    assert_eq!(err.to_string(), "invalid DNA character in input k-mer: GTN");
+    // k-mers before the invalid one were already added (ACG and CGT are
+    // reverse complements, so there's only one hash)
+    assert_eq!(mh.size(), 1);
+}
+
+#[test]
+fn add_protein() {
+    let mut mh = KmerMinHash::new(100, 9, true, 42, 0, false);
+    mh.add_protein(b"mrvlkFGGTS").unwrap();
+    assert_eq!(mh.size(), 8);
+
+    // same k-mers as translating the DNA sequence in all six frames
+    let mut translated = KmerMinHash::new(100, 9, true, 42, 0, false);
+    translated.add_sequence(b"ATGCGTGTTCTGAAA", false).unwrap();
+    let mut prot = KmerMinHash::new(100, 9, true, 42, 0, false);
+    prot.add_protein(b"MRVLK").unwrap();
+    assert_eq!(prot.count_common(&translated).unwrap(), 3);
+
+    // shorter than k, nothing to add
+    mh.add_protein(b"MR").unwrap();
+    assert_eq!(mh.size(), 8);
+}
+
+#[test]
+fn add_protein_invalid() {
+    let mut mh = KmerMinHash::new(100, 9, true, 42, 0, false);
+    let err = mh.add_protein(b"MRV1KF").unwrap_err();
+    assert_error_code!(err, InvalidProt);
+    match err.downcast_ref::<SourmashError>() {
+        Some(SourmashError::InvalidProt { message }) => assert_eq!(message, "'1' at position 3"),
+        _ => panic!("expected an InvalidProt error"),
+    }
+    // the whole sequence is validated before anything is added
+    assert_eq!(mh.size(), 0);
+
+    let mut dna = KmerMinHash::new(100, 9, false, 42, 0, false);
+    let err = dna.add_protein(b"MRVLKF").unwrap_err();
+    assert_error_code!(err, MismatchDNAProt);
+    assert_eq!(dna.size(), 0);
+}
+
+#[test]
+fn reduced_alphabets() {
+    let mut protein = KmerMinHash::new(100, 9, true, 42, 0, false);
+    let mut dayhoff = protein.clone();
+    dayhoff.dayhoff = true;
+    let mut hp = protein.clone();
+    hp.hp = true;
+
+    // CAG and CGA are the same k-mer in Dayhoff (abb) and HP (phh)
+    for mh in &mut [&mut protein, &mut dayhoff, &mut hp] {
+        let mut other = mh.clone();
+        mh.add_protein(b"CAG").unwrap();
+        other.add_protein(b"CGA").unwrap();
+        let expected = if mh.molecule() == "protein" { 0.0 } else { 1.0 };
+        assert_eq!(mh.compare(&other).unwrap(), expected);
+    }
+
+    // translated sequences are encoded too
+    let mut translated = KmerMinHash::new(100, 9, true, 42, 0, false);
+    translated.dayhoff = true;
+    translated.add_sequence(b"ATGCGTGTTCTGAAA", false).unwrap();
+    let mut prot = translated.copy_empty();
+    prot.add_protein(b"MRVLK").unwrap();
+    assert_eq!(prot.count_common(&translated).unwrap(), 3);
+
+    assert!(dayhoff.check_compatible(&protein).is_err());
+    assert!(dayhoff.check_compatible(&hp).is_err());
+    assert!(hp.check_compatible(&hp).is_ok());
+
+    // the alphabet can only change while the sketch is empty
+    let err = protein.set_dayhoff(true).unwrap_err();
+    assert_error_code!(err, NonEmptyMinHash);
+    assert!(hp.set_hp(false).is_err());
+    assert!(dayhoff.set_hp(true).is_err());
+    assert_eq!(dayhoff.molecule(), "dayhoff");
+    dayhoff.set_dayhoff(true).unwrap();
+
+    let mut empty = dayhoff.copy_empty();
+    empty.set_hp(true).unwrap();
+    assert_eq!(empty.molecule(), "hp");
+    empty.set_hp(false).unwrap();
+    assert_eq!(empty.molecule(), "protein");
+}
+
+#[test]
+fn serialize_molecule() {
+    let mut mh = KmerMinHash::new(100, 9, true, 42, 0, false);
+    mh.hp = true;
+    mh.add_protein(b"MRVLKF").unwrap();
+
+    let json = serde_json::to_string(&mh).unwrap();
+    assert!(json.contains(r#""molecule":"hp""#));
+    let loaded: KmerMinHash = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded, mh);
+
+    mh.hp = false;
+    mh.dayhoff = true;
+    let json = serde_json::to_string(&mh).unwrap();
+    assert!(json.contains(r#""molecule":"dayhoff""#));
+    let loaded: KmerMinHash = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.molecule(), "dayhoff");
+}
+
+#[test]
+fn translation_tables() {
+    let mut standard = KmerMinHash::new(100, 9, true, 42, 0, false);
+    let mut mito = standard.clone();
+    mito.set_translation_table(2).unwrap();
+
+    // TGA is a stop codon in the standard code, and tryptophan in the
+    // vertebrate mitochondrial code
+    standard.add_sequence(b"ATGTGAAAA", false).unwrap();
+    mito.add_sequence(b"ATGTGAAAA", false).unwrap();
+
+    let mut prot = standard.copy_empty();
+    prot.add_protein(b"MWK").unwrap();
+    assert_eq!(prot.count_common(&mito).unwrap(), 1);
+    assert_eq!(prot.count_common(&standard).unwrap(), 0);
+
+    let json = serde_json::to_string(&mito).unwrap();
+    assert!(json.contains(r#""translation_table":2"#));
+    let loaded: KmerMinHash = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded, mito);
+    assert!(!serde_json::to_string(&standard)
+        .unwrap()
+        .contains("translation_table"));
+
+    let err = mito.set_translation_table(7).unwrap_err();
+    assert_error_code!(err, InvalidTranslationTable);
+    assert_eq!(mito.translation_table, 2);
+}
+
+#[test]
+fn ambiguous_codons() {
+    let mut mh = KmerMinHash::new(100, 9, true, 42, 0, false);
+    mh.add_sequence(b"ATGNNNAAA", false).unwrap();
+
+    let mut prot = mh.copy_empty();
+    prot.add_protein(b"MXK").unwrap();
+    assert_eq!(prot.count_common(&mh).unwrap(), 1);
+}
+
+#[test]
+fn hash_functions() {
+    let mut murmur = KmerMinHash::new(100, 3, false, 42, 0, false);
+    let mut xxhash = murmur.clone();
+    xxhash.hash_function = HashFunction::XxHash64;
+
+    murmur.add_sequence(b"ACGTAC", false).unwrap();
+    xxhash.add_sequence(b"ACGTAC", false).unwrap();
+    assert_eq!(murmur.size(), xxhash.size());
+    assert_ne!(murmur.mins, xxhash.mins);
+    assert!(murmur
+        .mins
+        .contains(&HashFunction::Murmur64.hash(b"ACG", 42)));
+    assert!(xxhash
+        .mins
+        .contains(&HashFunction::XxHash64.hash(b"ACG", 42)));
+
+    let err = murmur.compare(&xxhash).unwrap_err();
+    assert_error_code!(err, MismatchHashFunction);
+    assert!(murmur.merge(&xxhash).is_err());
+
+    // the hash function can only change while the sketch is empty
+    let err = murmur
+        .set_hash_function(HashFunction::XxHash64)
+        .unwrap_err();
+    assert_error_code!(err, NonEmptyMinHash);
+    assert_eq!(murmur.hash_function, HashFunction::Murmur64);
+    murmur.set_hash_function(HashFunction::Murmur64).unwrap();
+
+    let mut empty = murmur.copy_empty();
+    empty.set_hash_function(HashFunction::XxHash64).unwrap();
+    assert_eq!(empty.hash_function, HashFunction::XxHash64);
+
+    assert_eq!(HashFunction::try_from(2).unwrap(), HashFunction::XxHash64);
+    assert!(HashFunction::try_from(0).is_err());
+}
+
+#[test]
+fn set_operations() {
+    let mut a = KmerMinHash::new(0, 21, false, 42, max_hash_for_scaled(1), true);
+    a.add_many_with_abund(&[(1, 2), (3, 1), (5, 4)]).unwrap();
+    let mut b = a.copy_empty();
+    b.add_many_with_abund(&[(3, 2), (4, 1), (5, 1)]).unwrap();
+
+    let union = a.union(&b).unwrap();
+    assert_eq!(union.mins, vec![1, 3, 4, 5]);
+    assert_eq!(union.abunds, Some(vec![2, 3, 1, 5]));
+
+    let difference = a.difference(&b).unwrap();
+    assert_eq!(difference.mins, vec![1]);
+    assert_eq!(difference.abunds, Some(vec![2]));
+
+    let symmetric = a.symmetric_difference(&b).unwrap();
+    assert_eq!(symmetric.mins, vec![1, 4]);
+    assert_eq!(symmetric.abunds, Some(vec![2, 1]));
+
+    // union is the same as merging
+    let mut merged = a.clone();
+    merged.merge(&b).unwrap();
+    assert_eq!(merged, union);
+
+    // sketches without abundances stay that way
+    let mut flat = KmerMinHash::new(0, 21, false, 42, max_hash_for_scaled(1), false);
+    flat.add_many(&[1, 2]).unwrap();
+    let union = flat.union(&a).unwrap();
+    assert_eq!(union.mins, vec![1, 2, 3, 5]);
+    assert_eq!(union.abunds, None);
+}
+
+#[test]
+fn set_operations_num() {
+    let mut a = KmerMinHash::new(3, 21, false, 42, 0, false);
+    a.add_many(&[10, 20, 30]).unwrap();
+    let mut b = a.copy_empty();
+    b.add_many(&[5, 20, 25]).unwrap();
+
+    // only the num smallest hashes are kept
+    assert_eq!(a.union(&b).unwrap().mins, vec![5, 10, 20]);
+    assert_eq!(a.difference(&b).unwrap().mins, vec![10, 30]);
+    assert_eq!(a.symmetric_difference(&b).unwrap().mins, vec![5, 10, 25]);
+
+    let mut other = KmerMinHash::new(3, 31, false, 42, 0, false);
+    other.add_hash(10);
+    assert!(a.difference(&other).is_err());
+}
+
+#[test]
+fn remove_hashes() {
+    let mut mh = KmerMinHash::new(0, 21, false, 42, max_hash_for_scaled(1), true);
+    mh.add_many_with_abund(&[(1, 2), (3, 1), (5, 4)]).unwrap();
+
+    mh.remove_hash(3);
+    assert_eq!(mh.mins, vec![1, 5]);
+    assert_eq!(mh.abunds, Some(vec![2, 4]));
+
+    // missing hashes are ignored
+    mh.remove_many(&[1, 2, 7]).unwrap();
+    assert_eq!(mh.mins, vec![5]);
+    assert_eq!(mh.abunds, Some(vec![4]));
+}
+
+#[test]
+fn sorted_iterators() {
+    let left = [1, 3, 5, 7];
+    let right = [2, 3, 7, 8];
+
+    let union: Vec<_> = Union::new(left.iter(), right.iter()).cloned().collect();
+    assert_eq!(union, vec![1, 2, 3, 5, 7, 8]);
+
+    let difference: Vec<_> = Difference::new(left.iter(), right.iter())
+        .cloned()
+        .collect();
+    assert_eq!(difference, vec![1, 5]);
+
+    let intersection: Vec<_> = Intersection::new(left.iter(), right.iter())
+        .cloned()
+        .collect();
+    assert_eq!(intersection, vec![3, 7]);
+}
+
+#[test]
+fn containment() {
+    let mut a = KmerMinHash::new(0, 21, false, 42, max_hash_for_scaled(1), false);
+    a.add_many(&[1, 2, 3, 4]).unwrap();
+    let mut b = a.copy_empty();
+    b.add_many(&[3, 4, 5, 6, 7, 8, 9, 10]).unwrap();
+
+    assert_eq!(a.contained_by(&b).unwrap(), 0.5);
+    assert_eq!(b.contained_by(&a).unwrap(), 0.25);
+    assert_eq!(a.max_containment(&b).unwrap(), 0.5);
+    assert_eq!(b.max_containment(&a).unwrap(), 0.5);
+
+    let empty = a.copy_empty();
+    assert_eq!(empty.contained_by(&a).unwrap(), 0.0);
+    assert_eq!(a.max_containment(&empty).unwrap(), 0.0);
+}
+
+#[test]
+fn ani_estimates() {
+    assert_eq!(containment_to_ani(1.0, 21), 1.0);
+    assert_eq!(containment_to_ani(0.0, 21), 0.0);
+    assert!((containment_to_ani(0.5, 21) - 0.5f64.powf(1.0 / 21.0)).abs() < 1e-12);
+    // identical sets have the same Jaccard and containment
+    assert_eq!(jaccard_to_ani(1.0, 21), 1.0);
+    assert!((jaccard_to_ani(1.0 / 3.0, 21) - containment_to_ani(0.5, 21)).abs() < 1e-12);
+
+    let mut a = KmerMinHash::new(0, 21, false, 42, max_hash_for_scaled(1), false);
+    a.add_many(&[1, 2, 3, 4]).unwrap();
+    let mut b = a.copy_empty();
+    b.add_many(&[3, 4, 5, 6, 7, 8]).unwrap();
+    assert_eq!(a.containment_ani(&b).unwrap(), containment_to_ani(0.5, 21));
+    assert_eq!(
+        b.max_containment_ani(&a).unwrap(),
+        containment_to_ani(0.5, 21)
+    );
+    assert_eq!(a.jaccard_ani(&b).unwrap(), jaccard_to_ani(0.25, 21));
+
+    // protein k-mers are ksize / 3 residues long
+    let mut prot = KmerMinHash::new(0, 21, true, 42, max_hash_for_scaled(1), false);
+    prot.add_many(&[1, 2]).unwrap();
+    let mut other = prot.copy_empty();
+    other.add_many(&[1]).unwrap();
+    assert_eq!(
+        prot.containment_ani(&other).unwrap(),
+        containment_to_ani(0.5, 7)
+    );
+}
+
+#[test]
+fn similarity_intervals() {
+    let mut a = KmerMinHash::new(0, 21, false, 42, max_hash_for_scaled(100), false);
+    a.add_many(&(1..=100).collect::<Vec<u64>>()).unwrap();
+    let mut b = a.copy_empty();
+    b.add_many(&(51..=150).collect::<Vec<u64>>()).unwrap();
+
+    let jaccard = a.jaccard_estimate(&b, 0.95).unwrap();
+    assert_eq!(jaccard.value, a.jaccard(&b).unwrap());
+    assert!(jaccard.low < jaccard.value && jaccard.value < jaccard.high);
+
+    let containment = a.containment_estimate(&b, 0.95).unwrap();
+    assert_eq!(containment.value, a.contained_by(&b).unwrap());
+    assert!(containment.low < 0.5 && 0.5 < containment.high);
+
+    // higher confidence, wider interval
+    let wide = a.containment_estimate(&b, 0.99).unwrap();
+    assert!(wide.low < containment.low && containment.high < wide.high);
+
+    let ani = containment.to_ani(21);
+    assert_eq!(ani.value, a.containment_ani(&b).unwrap());
+    assert!(ani.low < ani.value && ani.value < ani.high);
+}
+
+#[test]
+fn abundance_tools() {
+    let mut mh = KmerMinHash::new(0, 21, false, 42, max_hash_for_scaled(1), true);
+    mh.add_many_with_abund(&[(1, 1), (2, 5), (3, 1), (4, 2), (5, 9)])
+        .unwrap();
+
+    let filtered = mh.filter_abundance(2, None).unwrap();
+    assert_eq!(filtered.mins, vec![2, 4, 5]);
+    assert_eq!(filtered.abunds, Some(vec![5, 2, 9]));
+
+    let filtered = mh.filter_abundance(2, Some(5)).unwrap();
+    assert_eq!(filtered.mins, vec![2, 4]);
+    assert_eq!(filtered.abunds, Some(vec![5, 2]));
+
+    // filtered sketches don't report the cardinality of the input
+    let mut tracked = mh.copy_empty();
+    tracked.track_cardinality(12).unwrap();
+    tracked.add_many_with_abund(&[(1, 1), (2, 5)]).unwrap();
+    assert!(tracked.cardinality().unwrap() > 0);
+    assert_eq!(
+        tracked.filter_abundance(2, None).unwrap().cardinality(),
+        None
+    );
+
+    assert_eq!(
+        mh.abundance_histogram().unwrap(),
+        vec![(1, 2), (2, 1), (5, 1), (9, 1)]
+    );
+
+    let flat = mh.flatten();
+    assert_eq!(flat.mins, mh.mins);
+    assert_eq!(flat.abunds, None);
+
+    for err in &[
+        flat.filter_abundance(2, None).unwrap_err(),
+        flat.abundance_histogram().unwrap_err(),
+    ] {
+        assert_error_code!(err, NeedsAbundanceTracking);
+    }
+}
+
+#[test]
+fn abundance_of_largest_hash() {
+    let mut mh = KmerMinHash::new(2, 21, false, 42, 0, true);
+    for hash in &[10, 20, 20, 30, 20] {
+        mh.add_hash(*hash);
+    }
+
+    // the sketch is full, but the largest hash is still counted
+    assert_eq!(mh.mins, vec![10, 20]);
+    assert_eq!(mh.abunds, Some(vec![1, 3]));
+}
+
+#[test]
+fn cardinality() {
+    let mut mh = KmerMinHash::new(0, 21, false, 42, max_hash_for_scaled(100), false);
+    assert_eq!(mh.cardinality(), None);
+    mh.track_cardinality(12).unwrap();
+    assert!(mh.track_cardinality(20).is_err());
+    assert_eq!(mh.cardinality(), Some(0));
+
+    let mut a = mh.clone();
+    let mut b = mh.clone();
+    for i in 0..20_000u64 {
+        let kmer = format!("{:021}", i);
+        a.add_word(kmer.as_bytes());
+        if i >= 10_000 {
+            b.add_word(kmer.as_bytes());
+        }
+    }
+
+    let estimate = a.cardinality().unwrap() as f64;
+    assert!((estimate - 20_000.).abs() / 20_000. < 0.05);
+
+    // downsampling keeps the estimate for the whole input
+    let downsampled = a.downsample_scaled(1000).unwrap();
+    assert_eq!(downsampled.cardinality(), a.cardinality());
+
+    let mut merged = b.clone();
+    merged.merge(&a).unwrap();
+    assert_eq!(merged.cardinality(), a.cardinality());
+    assert_eq!(a.difference(&b).unwrap().cardinality(), None);
+
+    // merging a sketch without an estimator adds its hashes to the one we
+    // have, so the estimate is at least the number of hashes in the union
+    let mut small = KmerMinHash::new(0, 21, false, 42, max_hash_for_scaled(1), false);
+    small.track_cardinality(12).unwrap();
+    let mut plain = KmerMinHash::new(0, 21, false, 42, max_hash_for_scaled(1), false);
+    for i in 0..5_000u64 {
+        let kmer = format!("{:021}", i);
+        if i < 100 {
+            small.add_word(kmer.as_bytes());
+        }
+        plain.add_word(kmer.as_bytes());
+    }
+    for merged in &[small.union(&plain).unwrap(), plain.union(&small).unwrap()] {
+        assert_eq!(merged.mins.len(), 5_000);
+        assert!(merged.cardinality().unwrap() >= merged.mins.len() as u64);
+    }
+
+    // empty copies don't track the cardinality
+    assert_eq!(a.copy_empty().cardinality(), None);
+
+    let json = serde_json::to_string(&a).unwrap();
+    let loaded: KmerMinHash = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.hll, a.hll);
+
+    // sketches without an estimator don't write it
+    let plain = KmerMinHash::new(0, 21, false, 42, max_hash_for_scaled(100), false);
+    assert!(!serde_json::to_string(&plain).unwrap().contains("hll"));
+}
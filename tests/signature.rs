@@ -1,11 +1,14 @@
 use serde_json;
 
-
 use std::fs::File;
 use std::io::BufReader;
 use std::path::PathBuf;
 
-use sourmash::{KmerMinHash, Signature};
+use sourmash::assert_error_code;
+use sourmash::errors::SourmashErrorCode;
+use sourmash::file::CompressionFormat;
+use sourmash::selection::{Pattern, Selection};
+use sourmash::{binary, max_hash_for_scaled, HashFunction, KmerMinHash, Signature};
 
 #[test]
 fn load_signature() {
@@ -30,3 +33,315 @@ fn load_signature() {
     }
     assert_eq!(sig.signatures.len(), 4);
 }
+
+#[test]
+fn load_signatures_downsample() {
+    let mut mh = KmerMinHash::new_scaled(10, 21, false, 42, false);
+    mh.add_many(&[1, max_hash_for_scaled(100), max_hash_for_scaled(20)])
+        .unwrap();
+    let sig = Signature {
+        signatures: vec![mh],
+        ..Default::default()
+    };
+    let buf = serde_json::to_vec(&vec![sig]).unwrap();
+
+    let sigs = Signature::load_signatures(&mut &buf[..], 21, None, Some(100)).unwrap();
+    assert_eq!(sigs[0].signatures[0].scaled(), 100);
+    assert_eq!(
+        sigs[0].signatures[0].mins,
+        vec![1, max_hash_for_scaled(100)]
+    );
+
+    // coarser sketches are left alone
+    let sigs = Signature::load_signatures(&mut &buf[..], 21, None, Some(5)).unwrap();
+    assert_eq!(sigs[0].signatures[0].scaled(), 10);
+}
+
+#[test]
+fn load_signatures_moltype() {
+    let mut protein = KmerMinHash::new(100, 21, true, 42, 0, false);
+    protein.add_protein(b"MRVLKFGGTS").unwrap();
+    let mut dayhoff = protein.clone();
+    dayhoff.dayhoff = true;
+    let sig = Signature {
+        signatures: vec![protein, dayhoff],
+        ..Default::default()
+    };
+    let buf = serde_json::to_vec(&vec![sig]).unwrap();
+
+    for moltype in &["protein", "dayhoff", "DAYHOFF"] {
+        let sigs = Signature::load_signatures(&mut &buf[..], 21, Some(moltype), None).unwrap();
+        assert_eq!(sigs.len(), 1);
+        assert_eq!(
+            sigs[0].signatures[0].molecule().to_lowercase(),
+            moltype.to_lowercase()
+        );
+    }
+
+    let sigs = Signature::load_signatures(&mut &buf[..], 21, Some("hp"), None).unwrap();
+    assert!(sigs.is_empty());
+}
+
+#[test]
+fn hash_function_roundtrip() {
+    let mut mh = KmerMinHash::new(100, 21, false, 42, 0, false);
+    mh.hash_function = HashFunction::XxHash64;
+    mh.add_sequence(b"ACGTACGTACGTACGTACGTACGTAC", false)
+        .unwrap();
+    let sig = Signature {
+        hash_function: HashFunction::XxHash64.as_str().into(),
+        signatures: vec![mh.clone()],
+        ..Default::default()
+    };
+
+    let buf = serde_json::to_vec(&vec![sig]).unwrap();
+    assert!(String::from_utf8_lossy(&buf).contains(r#""hash_function":"0.xxhash64""#));
+    let sigs = Signature::from_reader(&mut &buf[..]).unwrap();
+    assert_eq!(sigs[0].hash_function, "0.xxhash64");
+    assert_eq!(sigs[0].signatures[0], mh);
+
+    let buf = String::from_utf8(buf)
+        .unwrap()
+        .replace("0.xxhash64", "0.sha1");
+    assert!(Signature::from_reader(&mut buf.as_bytes()).is_err());
+
+    // the saved hash function comes from the sketches, not from the field
+    let mut sig = Signature {
+        signatures: vec![mh.clone()],
+        ..Default::default()
+    };
+    assert_eq!(sig.hash_function, "0.murmur64");
+    assert_eq!(sig.hash_function_name().unwrap(), "0.xxhash64");
+
+    let buf = serde_json::to_vec(&vec![sig.clone()]).unwrap();
+    let sigs = Signature::from_reader(&mut &buf[..]).unwrap();
+    assert_eq!(sigs[0].hash_function, "0.xxhash64");
+    assert_eq!(sigs[0].signatures[0], mh);
+
+    let mut buf = Vec::new();
+    binary::write_signatures(&mut buf, &[sig.clone()]).unwrap();
+    let sigs = Signature::from_reader(&mut &buf[..]).unwrap();
+    assert_eq!(sigs[0].hash_function, "0.xxhash64");
+    assert_eq!(sigs[0].signatures[0], mh);
+
+    // sketches with different hash functions can't be saved together
+    let mut murmur = mh.copy_empty();
+    murmur.hash_function = HashFunction::Murmur64;
+    sig.signatures.push(murmur);
+    let err = sig.hash_function_name().unwrap_err();
+    assert_error_code!(err, MismatchHashFunction);
+    assert!(serde_json::to_vec(&sig).is_err());
+    assert!(binary::write_signatures(&mut Vec::new(), &[sig]).is_err());
+}
+
+#[test]
+fn load_selected() {
+    let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    filename.push("tests/data/genome-s10+s11.sig");
+
+    let selection = Selection {
+        md5sum: Some("8a619747".into()),
+        name: Some(Pattern::substring("s10")),
+        ..Default::default()
+    };
+    let mut reader = BufReader::new(File::open(&filename).unwrap());
+    let sigs = Signature::load_selected(&mut reader, &selection).unwrap();
+
+    assert_eq!(sigs.len(), 1);
+    let mh = &sigs[0].signatures[0];
+    assert_eq!((mh.ksize, mh.molecule()), (21, "DNA"));
+    assert_eq!(mh.md5sum(), "8a619747693c045afde376263841806b");
+
+    let selection = Selection {
+        ksize: Some(30),
+        ..Default::default()
+    };
+    let mut reader = BufReader::new(File::open(&filename).unwrap());
+    let sigs = Signature::load_selected(&mut reader, &selection).unwrap();
+    assert_eq!(sigs.len(), 2);
+}
+
+#[test]
+fn load_checked_md5sum() {
+    let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    filename.push("tests/data/genome-s10+s11.sig");
+
+    let mut reader = BufReader::new(File::open(&filename).unwrap());
+    let sigs = Signature::from_reader_checked(&mut reader).unwrap();
+    assert_eq!(sigs[0].signatures.len(), 4);
+
+    let mut mh = KmerMinHash::new(100, 21, false, 42, 0, false);
+    mh.add_many(&[1, 2, 3]).unwrap();
+    let sig = Signature {
+        signatures: vec![mh],
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&vec![sig]).unwrap();
+    assert!(Signature::from_reader_checked(&mut json.as_bytes()).is_ok());
+
+    // changing the hashes without updating the md5sum
+    let tampered = json.replace("[1,2,3]", "[1,2,4]");
+    assert!(Signature::from_reader(&mut tampered.as_bytes()).is_ok());
+    let err = Signature::from_reader_checked(&mut tampered.as_bytes()).unwrap_err();
+    assert_error_code!(err, InvalidMd5sum);
+}
+
+#[test]
+fn dedup_signatures() {
+    let mut a = KmerMinHash::new(100, 21, false, 42, 0, false);
+    a.add_many(&[1, 2, 3]).unwrap();
+    let mut b = a.clone();
+    b.add_hash(4);
+
+    let sig = |name: &str, mh: &KmerMinHash| Signature {
+        name: Some(name.into()),
+        signatures: vec![mh.clone()],
+        ..Default::default()
+    };
+    let sigs = vec![sig("a", &a), sig("b", &b), sig("a copy", &a)];
+
+    let names: Vec<String> = Signature::dedup(sigs)
+        .into_iter()
+        .map(|s| s.name.unwrap())
+        .collect();
+    assert_eq!(names, vec!["a", "b"]);
+}
+
+#[test]
+fn reject_invalid_sketches() {
+    let mut mh = KmerMinHash::new(3, 21, false, 42, 0, true);
+    mh.add_many(&[10, 20, 30]).unwrap();
+    let sig = Signature {
+        signatures: vec![mh],
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&vec![sig]).unwrap();
+    assert!(Signature::from_reader(&mut json.as_bytes()).is_ok());
+
+    let cases = vec![
+        (
+            json.replace("\"DNA\"", "\"RNA\""),
+            SourmashErrorCode::InvalidMolecule,
+        ),
+        (
+            json.replace("[10,20,30]", "[10,30,20]"),
+            SourmashErrorCode::UnsortedHashes,
+        ),
+        (
+            json.replace("[10,20,30]", "[10,20,20]"),
+            SourmashErrorCode::UnsortedHashes,
+        ),
+        (
+            json.replace("\"abundances\":[1,1,1]", "\"abundances\":[1,1]"),
+            SourmashErrorCode::InvalidAbundances,
+        ),
+        (
+            json.replace("[10,20,30]", "[10,20,30,40]")
+                .replace("[1,1,1]", "[1,1,1,1]"),
+            SourmashErrorCode::InvalidNum,
+        ),
+        (
+            json.replace("\"max_hash\":0", "\"max_hash\":25"),
+            SourmashErrorCode::InvalidMaxHash,
+        ),
+    ];
+
+    for (corrupted, code) in cases {
+        assert_ne!(corrupted, json);
+        let err = Signature::from_reader(&mut corrupted.as_bytes()).unwrap_err();
+        assert_eq!(
+            SourmashErrorCode::from_error(&err) as u32,
+            code as u32,
+            "{}",
+            corrupted
+        );
+        assert!(serde_json::from_str::<Vec<Signature>>(&corrupted).is_err());
+    }
+}
+
+#[test]
+fn load_legacy_unsorted_sketches() {
+    // older sourmash versions saved scaled sketches with unsorted hashes
+    let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    filename.push("tests/data/.sbt.subset/004459575e3657bca8a3d0424545f082");
+    let sigs = Signature::from_path(&filename).unwrap();
+    assert!(sigs[0].signatures[0].mins.windows(2).all(|w| w[0] < w[1]));
+
+    let mut mh = KmerMinHash::new(0, 21, false, 42, max_hash_for_scaled(10), true);
+    mh.add_many_with_abund(&[(10, 1), (20, 2), (30, 3)])
+        .unwrap();
+    let sig = Signature {
+        signatures: vec![mh],
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&vec![sig]).unwrap();
+
+    // the stored md5sum matches the sorted hashes, so they are sorted (and
+    // the abundances with them)
+    let legacy = json
+        .replace("[10,20,30]", "[30,10,20]")
+        .replace("[1,2,3]", "[3,1,2]");
+    let sigs = Signature::from_reader(&mut legacy.as_bytes()).unwrap();
+    assert_eq!(sigs[0].signatures[0].mins, vec![10, 20, 30]);
+    assert_eq!(sigs[0].signatures[0].abunds, Some(vec![1, 2, 3]));
+
+    // otherwise unsorted hashes are still rejected
+    let tampered = json.replace("[10,20,30]", "[30,10,21]");
+    let err = Signature::from_reader(&mut tampered.as_bytes()).unwrap_err();
+    assert_error_code!(err, UnsortedHashes);
+}
+
+#[test]
+fn binary_roundtrip() {
+    let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    filename.push("tests/data/genome-s10+s11.sig");
+    let sigs = Signature::from_path(&filename).unwrap();
+
+    let mut buf = Vec::new();
+    binary::write_signatures(&mut buf, &sigs).unwrap();
+    assert!(buf.len() < std::fs::metadata(&filename).unwrap().len() as usize);
+
+    let loaded = Signature::from_reader(&mut &buf[..]).unwrap();
+    assert_eq!(loaded.len(), sigs.len());
+    for (loaded, sig) in loaded.iter().zip(&sigs) {
+        assert_eq!(loaded, sig);
+        assert_eq!(loaded.signatures, sig.signatures);
+    }
+}
+
+#[test]
+fn compressed_signatures() {
+    let mut filename = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    filename.push("tests/data/genome-s10+s11.sig");
+    let sigs = Signature::from_path(&filename).unwrap();
+
+    for ext in &["sig", "sig.gz", "sig.bz2", "sig.xz"] {
+        let mut path = std::env::temp_dir();
+        path.push(format!("sourmash-sig-{}.{}", std::process::id(), ext));
+        Signature::save_path(&sigs, &path).unwrap();
+        let size = std::fs::metadata(&path).unwrap().len();
+        let loaded = Signature::from_path(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, sigs);
+        assert_eq!(loaded[0].signatures, sigs[0].signatures);
+        if *ext != "sig" {
+            assert!(size < std::fs::metadata(&filename).unwrap().len());
+        }
+    }
+
+    // explicit format, no matter the extension
+    let mut path = std::env::temp_dir();
+    path.push(format!("sourmash-sig-{}.sig", std::process::id()));
+    Signature::save_path_compressed(&sigs, &path, CompressionFormat::Gzip).unwrap();
+    let mut reader = BufReader::new(File::open(&path).unwrap());
+    let loaded = Signature::from_reader_checked(&mut reader).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, sigs);
+
+    // binary signatures can be compressed too
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    binary::write_signatures(&mut gz, &sigs).unwrap();
+    let compressed = gz.finish().unwrap();
+    assert_eq!(Signature::from_reader(&mut &compressed[..]).unwrap(), sigs);
+}